@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use crate::methods::auth;
 use candid::Principal;
 use ic_cdk::{
@@ -8,8 +10,9 @@ use ic_cdk::{
             provisional::CanisterIdRecord,
         },
     },
-    caller, id, init, query, update,
+    caller, id, init, post_upgrade, query, update,
 };
+use ic_cdk_timers::{set_timer, set_timer_interval};
 
 use ic_scalable_canister::ic_scalable_misc::{
     enums::api_error_type::ApiError,
@@ -18,7 +21,7 @@ use ic_scalable_canister::ic_scalable_misc::{
 use ic_scalable_canister::{ic_methods, store::Data};
 
 use crate::{
-    store::{ENTRIES, STABLE_DATA},
+    store::{Store, ENTRIES, PROFILE_MIGRATION_BATCH_SIZE, STABLE_DATA},
     IDENTIFIER_KIND,
 };
 
@@ -63,7 +66,9 @@ pub fn __export_did_tmp_() -> String {
     use ic_canister_backup::models::*;
     use ic_cdk::api::management_canister::http_request::HttpResponse;
     use ic_scalable_canister::ic_scalable_misc::enums::api_error_type::ApiError;
+    use ic_scalable_canister::ic_scalable_misc::enums::sort_type::SortDirection;
     use ic_scalable_canister::ic_scalable_misc::models::http_models::HttpRequest;
+    use ic_scalable_canister::ic_scalable_misc::models::paged_response_models::PagedResponse;
     use shared::profile_models::*;
     export_service!();
     __export_service()
@@ -78,6 +83,58 @@ pub fn init(parent: Principal, name: String, identifier: usize) {
     STABLE_DATA.with(|data| {
         ic_methods::init(&data, parent, name, identifier);
     });
+    // A new canister has no profiles with relations on the deprecated string map
+    Store::mark_legacy_relations_migrated();
+    start_timers();
+}
+
+// Timers are not persisted over upgrades, so they are restarted after every upgrade
+#[post_upgrade]
+pub fn post_upgrade() {
+    Store::index_username_history();
+    Store::start_profile_migration();
+    start_timers();
+    set_timer(Duration::ZERO, migrate_profiles);
+}
+
+// Re-encode the stored profiles in the current storage version, in batches to stay within the instruction limit
+fn migrate_profiles() {
+    if !Store::migrate_profiles_batch(PROFILE_MIGRATION_BATCH_SIZE) {
+        set_timer(Duration::from_secs(1), migrate_profiles);
+    }
+}
+
+// Method to start the recurring jobs of the canister
+fn start_timers() {
+    // Erase the profiles of which the deletion grace period has passed
+    set_timer_interval(
+        Duration::from_secs(60 * 60),
+        Store::process_profile_deletions,
+    );
+    // Expire the friend requests that passed the time to live
+    set_timer_interval(Duration::from_secs(60 * 60), Store::expire_friend_requests);
+    // Forget the friend request send times that no longer count towards the rate limits
+    set_timer_interval(
+        Duration::from_secs(60 * 60),
+        Store::prune_friend_requests_sent,
+    );
+    // Check the social graph for inconsistencies, the report can be read by admins
+    set_timer_interval(Duration::from_secs(24 * 60 * 60), || {
+        ic_cdk::spawn(async {
+            Store::check_graph_consistency(false).await;
+        })
+    });
+    // Remove the profile changes that passed the retention period
+    set_timer_interval(
+        Duration::from_secs(24 * 60 * 60),
+        Store::prune_profile_changes,
+    );
+    // Deliver the notifications in the outbox, failed deliveries are retried with backoff
+    set_timer_interval(Duration::from_secs(60), || {
+        ic_cdk::spawn(async {
+            Store::process_notification_outbox().await;
+        })
+    });
 }
 
 // Method used to save the candid interface to a file
@@ -3,7 +3,9 @@ pub static IDENTIFIER_KIND: &str = "pfe";
 pub mod backup;
 pub mod default;
 pub mod methods;
+mod notification_sink;
 pub mod read_stores;
 mod stable_backup;
 pub mod store;
+mod system_api;
 pub mod validation;
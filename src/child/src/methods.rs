@@ -2,16 +2,30 @@ use candid::Principal;
 
 use ic_cdk::{caller, query, update};
 use ic_scalable_canister::ic_scalable_misc::enums::api_error_type::ApiError;
+use ic_scalable_canister::ic_scalable_misc::enums::sort_type::SortDirection;
+use ic_scalable_canister::ic_scalable_misc::models::paged_response_models::PagedResponse;
 
 use shared::profile_models::{
-    FriendRequestResponse, PostProfile, PostWallet, ProfileFilter, ProfileResponse, RelationType,
-    UpdateProfile,
+    ClearRelationsRequest, ClearRelationsSummary, ContactGroupResponse, ContactNoteResponse,
+    CursorPagedRelationsResponse, FieldVisibility, FriendRequestLimits, FriendRequestResponse,
+    FriendRequestWithProfileResponse, FriendSuggestion, GraphConsistencyReport, LocalNotification,
+    NotificationEnvironment, NotificationPreferences, OutboxNotificationResponse, PostContactGroup,
+    PostContactNote, PostProfile, PostWallet, ProfileChangeResponse, ProfileDeletion,
+    ProfileFilter, ProfileRecommendation, ProfileResponse, RecommendationSource, RelationResponse,
+    RelationSort, RelationType, RelationsVisibility, UpdateProfile,
 };
 
 use crate::store::STABLE_DATA;
 
 use super::store::Store;
 
+// Methods that can fail return `Result<_, ApiError>`, the `tag` of the error is a stable code (for example
+// `PROFILE_NOT_FOUND` or `REQUEST_NOT_FOUND`) that clients can match on. Methods that returned something else
+// keep their old result and are marked as deprecated, the replacement has the same name with a `_v2` suffix.
+// Out of scope are the methods that can not fail once they run: queries that return a list or an option,
+// where nothing visible to the caller is an empty result, and the admin and controller methods,
+// which are rejected by their guard before they run.
+
 // This method is used to add a profile to the canister,
 // The method is async because it optionally creates a new canister is created
 #[update(guard = "auth")]
@@ -25,25 +39,25 @@ pub async fn add_profile(
 // This method is used to get a single profile by an user principal
 #[query]
 pub fn get_profile_by_user_principal(principal: Principal) -> Result<ProfileResponse, ApiError> {
-    Store::get_profile_by_user_principal(principal)
+    Store::get_profile_by_user_principal(caller(), principal)
 }
 
 // This method is used to get a single profile by an identifier
 #[query]
 pub fn get_profile_by_identifier(id: Principal) -> Result<ProfileResponse, ApiError> {
-    Store::get_profile_by_identifier(id)
+    Store::get_profile_by_identifier(caller(), id)
 }
 
 // This method is used to get multiple profiles by principals
 #[query]
 pub fn get_profiles_by_user_principal(principals: Vec<Principal>) -> Vec<ProfileResponse> {
-    Store::get_profiles_by_user_principal(principals)
+    Store::get_profiles_by_user_principal(caller(), principals)
 }
 
 // This method is used to get multiple profiles by identifiers
 #[query]
 pub fn get_profiles_by_identifier(identifiers: Vec<Principal>) -> Vec<ProfileResponse> {
-    Store::get_profiles_by_identifier(identifiers)
+    Store::get_profiles_by_identifier(caller(), identifiers)
 }
 
 // This method is used to edit a profile
@@ -52,6 +66,37 @@ pub fn edit_profile(update_profile: UpdateProfile) -> Result<ProfileResponse, Ap
     Store::update_profile(caller(), update_profile)
 }
 
+// This method is used to change the username of the profile, the old username stays reserved for a grace period
+#[update(guard = "auth")]
+pub fn change_username(username: String) -> Result<ProfileResponse, ApiError> {
+    Store::change_username(caller(), username)
+}
+
+// This method is used to get a single profile by its current or a previous username
+#[query]
+pub fn resolve_username(username: String) -> Result<ProfileResponse, ApiError> {
+    Store::resolve_username(caller(), username)
+}
+
+// This method is used to delete the profile of the caller,
+// when not deleted immediately the deletion can be cancelled during the grace period
+#[update(guard = "auth")]
+pub fn delete_profile(immediately: bool) -> Result<ProfileDeletion, ApiError> {
+    Store::delete_profile(caller(), immediately)
+}
+
+// This method is used to cancel a scheduled deletion of the profile
+#[update(guard = "auth")]
+pub fn cancel_profile_deletion() -> Result<bool, ApiError> {
+    Store::cancel_profile_deletion(caller())
+}
+
+// This method is used to get the scheduled deletion of the profile, if any
+#[query]
+pub fn get_profile_deletion() -> Option<ProfileDeletion> {
+    Store::get_profile_deletion(caller())
+}
+
 // This method is used to add a wallet reference to the profile
 #[update(guard = "auth")]
 pub fn add_wallet(wallet: PostWallet) -> Result<ProfileResponse, ApiError> {
@@ -59,8 +104,17 @@ pub fn add_wallet(wallet: PostWallet) -> Result<ProfileResponse, ApiError> {
 }
 
 // This method is used to set a wallet as primary
+// deprecated: use `set_wallet_as_primary_v2` which returns the reason of a failure
 #[update(guard = "auth")]
 pub fn set_wallet_as_primary(wallet_principal: Principal) -> Result<(), ()> {
+    Store::set_wallet_as_primary(caller(), wallet_principal)
+        .map(|_| ())
+        .map_err(|_| ())
+}
+
+// This method is used to set a wallet as primary
+#[update(guard = "auth")]
+pub fn set_wallet_as_primary_v2(wallet_principal: Principal) -> Result<ProfileResponse, ApiError> {
     Store::set_wallet_as_primary(caller(), wallet_principal)
 }
 
@@ -108,19 +162,45 @@ pub fn add_friend_request(
     Store::add_friend_request(caller(), principal, message)
 }
 
+// deprecated: use `remove_friend_v2` which returns the reason of a failure
 #[update(guard = "auth")]
 pub fn remove_friend(principal: Principal) -> Result<bool, String> {
+    // Removing a principal that is not a friend always succeeded
+    match Store::remove_friend(caller(), principal) {
+        Err(ApiError::NotFound(error)) if error.tag == "FRIEND_NOT_FOUND" => Ok(true),
+        result => result.map_err(|_| "Friend not found".to_string()),
+    }
+}
+
+// This method is used to remove a friend, the friendship is removed on both sides
+#[update(guard = "auth")]
+pub fn remove_friend_v2(principal: Principal) -> Result<bool, ApiError> {
     Store::remove_friend(caller(), principal)
 }
 
+// deprecated: use `accept_friend_request_v2` which returns the reason of a failure
 #[update(guard = "auth")]
 pub fn accept_friend_request(id: u64) -> Result<bool, String> {
+    Store::accept_friend_request(caller(), id).map_err(|_| "Request not found".to_string())
+}
+
+// This method is used to accept a friend request send to the caller
+#[update(guard = "auth")]
+pub fn accept_friend_request_v2(id: u64) -> Result<bool, ApiError> {
     Store::accept_friend_request(caller(), id)
 }
 
+// deprecated: use `remove_friend_request_v2` which returns the reason of a failure
+// the principal argument is ignored, only the caller can withdraw its own friend requests
+#[update(guard = "auth")]
+pub fn remove_friend_request(_principal: Principal, id: u64) -> Result<bool, String> {
+    Store::remove_friend_request(caller(), id).map_err(|_| "Request not found".to_string())
+}
+
+// This method is used to withdraw a friend request send by the caller
 #[update(guard = "auth")]
-pub fn remove_friend_request(principal: Principal, id: u64) -> Result<bool, String> {
-    Store::remove_friend_request(principal, id)
+pub fn remove_friend_request_v2(id: u64) -> Result<bool, ApiError> {
+    Store::remove_friend_request(caller(), id)
 }
 
 #[query]
@@ -128,8 +208,83 @@ pub fn get_friend_requests() -> Vec<FriendRequestResponse> {
     Store::get_friend_requests(caller())
 }
 
+// This method is used to get the friend requests send to the caller, paged and sorted on creation date,
+// optionally with a summary of the profile of the requester
+#[query]
+pub fn get_incoming_friend_requests(
+    limit: usize,
+    page: usize,
+    sort: SortDirection,
+    include_profile: bool,
+) -> PagedResponse<FriendRequestWithProfileResponse> {
+    Store::get_incoming_friend_requests(caller(), limit, page, sort, include_profile)
+}
+
+// This method is used to get the friend requests send by the caller, paged and sorted on creation date,
+// optionally with a summary of the profile of the invited user
+#[query]
+pub fn get_outgoing_friend_requests(
+    limit: usize,
+    page: usize,
+    sort: SortDirection,
+    include_profile: bool,
+) -> PagedResponse<FriendRequestWithProfileResponse> {
+    Store::get_outgoing_friend_requests(caller(), limit, page, sort, include_profile)
+}
+
+// This method is used to get the principals that can use the admin methods next to the controllers
+#[query(guard = "is_admin")]
+pub fn get_admins() -> Vec<Principal> {
+    Store::get_admins()
+}
+
+// This method is used to allow a principal to use the admin methods
+#[update(guard = "is_controller")]
+pub fn add_admin(principal: Principal) -> Vec<Principal> {
+    Store::add_admin(principal)
+}
+
+// This method is used to stop a principal from using the admin methods
+#[update(guard = "is_controller")]
+pub fn remove_admin(principal: Principal) -> Vec<Principal> {
+    Store::remove_admin(principal)
+}
+
+// This method is used to get the limits for sending friend requests
+#[query]
+pub fn get_friend_request_limits() -> FriendRequestLimits {
+    Store::get_friend_request_limits()
+}
+
+// This method is used to change the limits for sending friend requests
+#[update(guard = "is_admin")]
+pub fn set_friend_request_limits(
+    limits: FriendRequestLimits,
+) -> Result<FriendRequestLimits, ApiError> {
+    Store::set_friend_request_limits(limits)
+}
+
+// This method is used to get the time in nanoseconds a friend request stays open before it expires
+#[query]
+pub fn get_friend_request_ttl() -> u64 {
+    Store::get_friend_request_ttl()
+}
+
+// This method is used to change the time in nanoseconds a friend request stays open before it expires
+#[update(guard = "is_admin")]
+pub fn set_friend_request_ttl(ttl: u64) -> Result<u64, ApiError> {
+    Store::set_friend_request_ttl(ttl)
+}
+
+// deprecated: use `decline_friend_request_v2` which returns the reason of a failure
 #[update(guard = "auth")]
 pub fn decline_friend_request(id: u64) -> Result<bool, String> {
+    Store::decline_friend_request(caller(), id).map_err(|_| "Request not found".to_string())
+}
+
+// This method is used to decline a friend request send to the caller
+#[update(guard = "auth")]
+pub fn decline_friend_request_v2(id: u64) -> Result<bool, ApiError> {
     Store::decline_friend_request(caller(), id)
 }
 
@@ -143,25 +298,270 @@ pub fn block_user(principal: Principal) -> Result<ProfileResponse, ApiError> {
     Store::block_user(caller(), principal)
 }
 
-// This method is used to get all relations of a specific type (Friend or Blocked)
+// This method is used to follow a user, the followed user gets a notification
+// only users with a profile on the same child canister can be followed, other users return `PROFILE_NOT_ON_CANISTER`
+#[update(guard = "auth")]
+pub fn follow(principal: Principal) -> Result<bool, ApiError> {
+    Store::follow(caller(), principal)
+}
+
+// This method is used to stop following a user
+#[update(guard = "auth")]
+pub fn unfollow(principal: Principal) -> Result<bool, ApiError> {
+    Store::unfollow(caller(), principal)
+}
+
+// This method is used to mute a user, notifications from the muted user are suppressed without the user knowing
+#[update(guard = "auth")]
+pub fn mute(principal: Principal) -> Result<bool, ApiError> {
+    Store::mute(caller(), principal)
+}
+
+// This method is used to unmute a user
+#[update(guard = "auth")]
+pub fn unmute(principal: Principal) -> Result<bool, ApiError> {
+    Store::unmute(caller(), principal)
+}
+
+// This method is used to get all users muted by the caller
+#[query]
+pub fn get_muted() -> Vec<Principal> {
+    Store::get_relations(caller(), RelationType::Muted)
+}
+
+// This method is used to get all relations of a specific type (Friend, Blocked, Follow, Follower or Muted)
 #[query]
 pub fn get_relations(relation_type: RelationType) -> Vec<Principal> {
     Store::get_relations(caller(), relation_type)
 }
 
-// This method is used to get relations count of a specific type (Friend or Blocked)
+// This method is used to get the relations of a specific type (Friend, Blocked, Follow, Follower or Muted) with the date they were created, paged and sorted
+#[query]
+pub fn get_paged_relations(
+    relation_type: RelationType,
+    limit: usize,
+    page: usize,
+    sort: RelationSort,
+) -> PagedResponse<RelationResponse> {
+    Store::get_paged_relations(caller(), relation_type, limit, page, sort)
+}
+
+// This method is used to get the friends the caller has in common with another user
+#[query]
+pub fn get_mutual_friends(principal: Principal) -> Vec<Principal> {
+    Store::get_mutual_friends(caller(), principal)
+}
+
+// This method is used to get friends of friends of the caller, ranked by the number of mutual friends
+#[query]
+pub fn get_friend_suggestions(limit: usize) -> Vec<FriendSuggestion> {
+    Store::get_friend_suggestions(caller(), limit)
+}
+
+// This method is used to get other public profiles that share skills, interests, causes or location with the caller
+#[query]
+pub fn recommend_profiles(limit: usize) -> Result<Vec<ProfileRecommendation>, ApiError> {
+    Store::recommend_profiles(caller(), limit)
+}
+
+// This method is used to get the relations of a specific type of any user with a summary of the related profiles,
+// the relations are only returned when the relations visibility of the user allows it
+#[query]
+pub fn get_relations_paged(
+    principal: Principal,
+    relation_type: RelationType,
+    limit: usize,
+    cursor: Option<Principal>,
+    sort: RelationSort,
+) -> Result<CursorPagedRelationsResponse, ApiError> {
+    Store::get_relations_paged(caller(), principal, relation_type, limit, cursor, sort)
+}
+
+// This method is used to get who can see the friends, follows and followers of the caller
+#[query]
+pub fn get_relations_visibility() -> RelationsVisibility {
+    Store::get_relations_visibility(caller())
+}
+
+// This method is used to set who can see the friends, follows and followers of the caller
+#[update(guard = "auth")]
+pub fn set_relations_visibility(
+    visibility: RelationsVisibility,
+) -> Result<RelationsVisibility, ApiError> {
+    Store::set_relations_visibility(caller(), visibility)
+}
+
+// This method is used to add a contact group (for example "close friends"), only friends can be members
+#[update(guard = "auth")]
+pub fn add_contact_group(
+    post_contact_group: PostContactGroup,
+) -> Result<ContactGroupResponse, ApiError> {
+    Store::add_contact_group(caller(), post_contact_group)
+}
+
+// This method is used to change the name and members of a contact group of the caller
+#[update(guard = "auth")]
+pub fn edit_contact_group(
+    id: u64,
+    post_contact_group: PostContactGroup,
+) -> Result<ContactGroupResponse, ApiError> {
+    Store::edit_contact_group(caller(), id, post_contact_group)
+}
+
+// This method is used to delete a contact group of the caller
+#[update(guard = "auth")]
+pub fn delete_contact_group(id: u64) -> Result<bool, ApiError> {
+    Store::delete_contact_group(caller(), id)
+}
+
+// This method is used to get the contact groups of the caller
+#[query]
+pub fn get_contact_groups() -> Vec<ContactGroupResponse> {
+    Store::get_contact_groups(caller())
+}
+
+// This method is used to set a private note and tags about a principal in the relations of the caller
+#[update(guard = "auth")]
+pub fn set_contact_note(
+    principal: Principal,
+    post_contact_note: PostContactNote,
+) -> Result<ContactNoteResponse, ApiError> {
+    Store::set_contact_note(caller(), principal, post_contact_note)
+}
+
+// This method is used to get the private note of the caller about a principal
+#[query]
+pub fn get_contact_note(principal: Principal) -> Result<ContactNoteResponse, ApiError> {
+    Store::get_contact_note(caller(), principal)
+}
+
+// This method is used to get all private notes of the caller
+#[query]
+pub fn get_contact_notes() -> Vec<ContactNoteResponse> {
+    Store::get_contact_notes(caller())
+}
+
+// This method is used to remove the private note of the caller about a principal
+#[update(guard = "auth")]
+pub fn clear_contact_note(principal: Principal) -> Result<bool, ApiError> {
+    Store::clear_contact_note(caller(), principal)
+}
+
+// This method is used to get who can see the fields of the profile of the caller
+#[query]
+pub fn get_field_visibility() -> Vec<FieldVisibility> {
+    Store::get_field_visibility(caller())
+}
+
+// This method is used to set who can see the fields of the profile of the caller,
+// a field can be visible to everyone, friends, one of the contact groups of the caller or only the caller
+#[update(guard = "auth")]
+pub fn set_field_visibility(
+    fields: Vec<FieldVisibility>,
+) -> Result<Vec<FieldVisibility>, ApiError> {
+    Store::set_field_visibility(caller(), fields)
+}
+
+// deprecated: use `get_relations_count_v2` which returns the reason a count is not available
 #[query]
 pub fn get_relations_count(principal: Principal, relation_type: RelationType) -> u64 {
-    Store::get_relations(principal, relation_type).len() as u64
+    Store::get_relations_count(caller(), principal, relation_type).unwrap_or(0)
+}
+
+// This method is used to get relations count of a specific type,
+// `Follow` counts the users the principal follows and `Follower` the users that follow the principal,
+// fails when the relations visibility of the principal does not allow the caller to see them
+#[query]
+pub fn get_relations_count_v2(
+    principal: Principal,
+    relation_type: RelationType,
+) -> Result<u64, ApiError> {
+    Store::get_relations_count(caller(), principal, relation_type)
 }
 
+// This method is used to request a short-lived nonce that is needed to clear all relations of the caller
 #[update(guard = "auth")]
-pub fn clear_relations(code: String) -> bool {
-    if code != "i_know_what_i_am_doing" {
-        return false;
-    } else {
-        return Store::clear_relations(caller());
-    }
+pub async fn request_clear_relations() -> Result<ClearRelationsRequest, ApiError> {
+    Store::request_clear_relations(caller()).await
+}
+
+// This method is used to clear all friends, blocks and follows of the caller on both sides of the relation,
+// returns a summary of the removed relations
+#[update(guard = "auth")]
+pub async fn confirm_clear_relations(nonce: String) -> Result<ClearRelationsSummary, ApiError> {
+    Store::confirm_clear_relations(caller(), nonce).await
+}
+
+// deprecated: use `request_clear_relations` and `confirm_clear_relations` which return a summary of the removed relations
+#[update(guard = "auth")]
+pub async fn clear_relations(code: String) -> bool {
+    Store::clear_relations(caller(), code).await
+}
+
+// This method is used by other child canisters to clear the relations of a principal stored on this canister
+#[update]
+pub async fn clear_relations_by_sibling(
+    principal: Principal,
+) -> Result<ClearRelationsSummary, ApiError> {
+    Store::clear_relations_by_sibling(caller(), principal).await
+}
+
+// This method is used to check the social graph on this canister for inconsistencies,
+// the found issues are only repaired when `repair` is set
+#[update(guard = "is_admin")]
+pub async fn check_graph_consistency(repair: bool) -> GraphConsistencyReport {
+    Store::check_graph_consistency(repair).await
+}
+
+// This method is used to get the report of the last social graph consistency check
+#[query(guard = "is_admin")]
+pub fn get_graph_consistency_report() -> Option<GraphConsistencyReport> {
+    Store::get_graph_consistency_report()
+}
+
+// This method is used to get where the notifications of this canister are send to
+#[query(guard = "is_admin")]
+pub fn get_notification_environment() -> NotificationEnvironment {
+    Store::get_notification_environment()
+}
+
+// This method is used to change where the notifications of this canister are send to,
+// `Disabled` records the notifications on this canister instead of sending them
+#[update(guard = "is_admin")]
+pub fn set_notification_environment(
+    environment: NotificationEnvironment,
+) -> NotificationEnvironment {
+    Store::set_notification_environment(environment)
+}
+
+// This method is used to get the notifications that were recorded on this canister instead of send, newest first
+#[query(guard = "is_admin")]
+pub fn get_local_notifications(limit: usize, page: usize) -> PagedResponse<LocalNotification> {
+    Store::get_local_notifications(limit, page)
+}
+
+// This method is used to get the notifications in the outbox of which the delivery failed at least once
+#[query(guard = "is_admin")]
+pub fn get_stuck_notifications(
+    limit: usize,
+    page: usize,
+) -> PagedResponse<OutboxNotificationResponse> {
+    Store::get_stuck_notifications(limit, page)
+}
+
+// This method is used to get the notifications of which all delivery attempts failed
+#[query(guard = "is_admin")]
+pub fn get_notification_dead_letters(
+    limit: usize,
+    page: usize,
+) -> PagedResponse<OutboxNotificationResponse> {
+    Store::get_notification_dead_letters(limit, page)
+}
+
+// This method is used to move a dead letter back to the outbox so its delivery is retried
+#[update(guard = "is_admin")]
+pub fn retry_dead_letter_notification(id: u64) -> Result<bool, ApiError> {
+    Store::retry_dead_letter_notification(id)
 }
 
 // This method is used to approve the code of conduct for the specific caller
@@ -182,6 +582,45 @@ pub fn approve_terms_of_service(version: u64) -> Result<bool, ApiError> {
     Store::approve_terms_of_service(caller(), version)
 }
 
+// This method is used to get which notifications the caller receives
+#[query(guard = "auth")]
+pub fn get_notification_preferences() -> Result<NotificationPreferences, ApiError> {
+    Store::get_notification_preferences(caller())
+}
+
+// This method is used to turn notifications on or off per event for the caller,
+// the preferences only apply to events on the child canister of the profile of the caller,
+// events from profiles on other child canisters are always notified
+#[update(guard = "auth")]
+pub fn set_notification_preferences(
+    preferences: NotificationPreferences,
+) -> Result<NotificationPreferences, ApiError> {
+    Store::set_notification_preferences(caller(), preferences)
+}
+
+// This method is used to export all data stored about the caller as a versioned JSON document
+// Data is send as byte array chunks ` (bytes, (chunk, last_chunk)) `, the chunks combined form the UTF-8 encoded document
+// The document reflects the state at the time of each call, fetch all chunks right after each other
+#[query]
+pub fn export_my_data(
+    chunk: usize,
+    max_bytes_per_chunk: usize,
+) -> Result<(Vec<u8>, (usize, usize)), ApiError> {
+    Store::export_my_data(caller(), chunk, max_bytes_per_chunk)
+}
+
+// This method is used to get the change log of a profile, only the owner of the profile and admins can access it
+#[query]
+pub fn get_profile_changes(identifier: Principal) -> Result<Vec<ProfileChangeResponse>, ApiError> {
+    Store::get_profile_changes(caller(), identifier)
+}
+
+// This method is used to get a profile as it was at a specific time, only the owner of the profile and admins can access it
+#[query]
+pub fn get_profile_at(identifier: Principal, timestamp: u64) -> Result<ProfileResponse, ApiError> {
+    Store::get_profile_at(caller(), identifier, timestamp)
+}
+
 // COMPOSITE_QUERY PREPARATION
 // This methods is used by the parent canister to get filtered profiles the (this) child canister
 // Data serialized and send as byte array chunks ` (bytes, (start_chunk, end_chunk)) `
@@ -199,9 +638,48 @@ fn get_chunked_data(
     Store::get_chunked_data(filters, chunk, max_bytes_per_chunk)
 }
 
+// COMPOSITE_QUERY PREPARATION
+// This method is used by the parent canister to get what the profiles on all child canisters are scored against
+#[query]
+fn get_recommendation_source_by_parent(principal: Principal) -> Option<RecommendationSource> {
+    if caller() != STABLE_DATA.with(|data| data.borrow().get().parent) {
+        return None;
+    }
+
+    Store::get_recommendation_source(principal)
+}
+
+// COMPOSITE_QUERY PREPARATION
+// This method is used by the parent canister to get the recommended profiles of the (this) child canister
+#[query]
+fn get_recommended_profiles_by_parent(
+    source: RecommendationSource,
+    limit: usize,
+) -> Vec<ProfileRecommendation> {
+    if caller() != STABLE_DATA.with(|data| data.borrow().get().parent) {
+        return vec![];
+    }
+
+    Store::get_recommended_profiles(source, limit)
+}
+
 pub fn auth() -> Result<(), String> {
     match caller() == Principal::anonymous() {
         true => Err("Unauthorized".to_string()),
         false => Ok(()),
     }
 }
+
+pub fn is_controller() -> Result<(), String> {
+    match ic_cdk::api::is_controller(&caller()) {
+        true => Ok(()),
+        false => Err("Unauthorized".to_string()),
+    }
+}
+
+pub fn is_admin() -> Result<(), String> {
+    match Store::is_admin(caller()) {
+        true => Ok(()),
+        false => Err("Unauthorized".to_string()),
+    }
+}
@@ -0,0 +1,185 @@
+use candid::{decode_one, encode_args, Principal};
+use ic_catalyze_notifications::models::{
+    Environment, NotificationDataResponse, SendNotificationData, SilentNotificationDataResponse,
+};
+use ic_cdk::api::call::call_raw;
+use shared::profile_models::{LocalNotification, OutboxNotification};
+
+use crate::store::{
+    LOCAL_NOTIFICATIONS, LOCAL_NOTIFICATIONS_LIMIT, NOTIFICATION_DEAD_LETTERS, NOTIFICATION_OUTBOX,
+    NOTIFICATION_OUTBOX_NEXT_ID,
+};
+use crate::system_api::time;
+
+// Method of the notification canister that stores a notification on behalf of the sender
+pub static ADD_NOTIFICATION_METHOD: &str = "add_notification_by_canister";
+// Method of the notification canister that only pushes a notification to the receivers
+pub static SILENT_NOTIFICATION_METHOD: &str = "silent_notification_by_canister";
+
+// Where the store sends its notifications to
+pub trait NotificationSink {
+    // Notification on behalf of the sender, stored by the notification canister
+    fn add_notification(&self, sender: Principal, data: SendNotificationData, metadata: String);
+    // Notification that is only pushed to the receivers and not stored
+    fn silent_notification(&self, data: SendNotificationData, metadata: String);
+}
+
+// Writes the notifications to the outbox in the same call as the state change,
+// a timer delivers them to the Catalyze notification canister of the environment
+pub struct OutboxNotificationSink;
+
+impl OutboxNotificationSink {
+    fn enqueue(sender: Option<Principal>, data: SendNotificationData, metadata: String) {
+        let now = time();
+        let id = Self::next_id();
+        NOTIFICATION_OUTBOX.with(|outbox| {
+            outbox.borrow_mut().insert(
+                id,
+                OutboxNotification {
+                    sender,
+                    data: serde_json::to_string(&data).unwrap_or_default(),
+                    metadata,
+                    attempts: 0,
+                    next_attempt_at: now,
+                    last_error: None,
+                    created_at: now,
+                },
+            );
+        });
+    }
+
+    // Method to get a new id for the outbox, the ids in use from before the counter existed are skipped
+    fn next_id() -> u64 {
+        let next_key = |last_key: Option<u64>| last_key.map_or(0, |key| key.saturating_add(1));
+        let in_use = next_key(
+            NOTIFICATION_OUTBOX.with(|outbox| outbox.borrow().last_key_value().map(|(key, _)| key)),
+        )
+        .max(next_key(NOTIFICATION_DEAD_LETTERS.with(|dead_letters| {
+            dead_letters.borrow().last_key_value().map(|(key, _)| key)
+        })));
+
+        NOTIFICATION_OUTBOX_NEXT_ID.with(|next_id| {
+            let mut next_id = next_id.borrow_mut();
+            let id = (*next_id.get()).max(in_use);
+            let _ = next_id.set(id.saturating_add(1));
+            id
+        })
+    }
+
+    // Method to deliver a notification from the outbox to the notification canister of the environment
+    pub async fn deliver(
+        environment: Environment,
+        notification: OutboxNotification,
+    ) -> Result<(), String> {
+        let (method, args) = Self::get_delivery_call(&notification)?;
+        let canister = Self::get_notification_canister(environment);
+
+        let response = call_raw(canister, method, args, 0)
+            .await
+            .map_err(|(code, message)| format!("{:?} - {}", code, message))?;
+
+        match notification.sender {
+            Some(_) => decode_one::<NotificationDataResponse>(&response).map(|_| ()),
+            None => decode_one::<SilentNotificationDataResponse>(&response).map(|_| ()),
+        }
+        .map_err(|err| err.to_string())
+    }
+
+    // Method to get the method and the candid encoded arguments the notification canister is called with
+    pub fn get_delivery_call(
+        notification: &OutboxNotification,
+    ) -> Result<(&'static str, Vec<u8>), String> {
+        let data: SendNotificationData =
+            serde_json::from_str(&notification.data).map_err(|err| err.to_string())?;
+        let metadata = notification.metadata.clone();
+
+        match notification.sender {
+            Some(sender) => {
+                encode_args((sender, data, metadata)).map(|args| (ADD_NOTIFICATION_METHOD, args))
+            }
+            None => encode_args((data, metadata)).map(|args| (SILENT_NOTIFICATION_METHOD, args)),
+        }
+        .map_err(|err| err.to_string())
+    }
+
+    // The same canisters the `ic_catalyze_notifications` client sends to, the client does not expose them
+    fn get_notification_canister(environment: Environment) -> Principal {
+        let canister = match environment {
+            Environment::Production => "jgn4p-7aaaa-aaaap-abona-cai",
+            Environment::Staging => "sswdm-viaaa-aaaap-abugq-cai",
+            Environment::Development => "s3viq-daaaa-aaaap-abuha-cai",
+        };
+        Principal::from_text(canister).unwrap_or(Principal::anonymous())
+    }
+}
+
+impl NotificationSink for OutboxNotificationSink {
+    fn add_notification(&self, sender: Principal, data: SendNotificationData, metadata: String) {
+        Self::enqueue(Some(sender), data, metadata);
+    }
+
+    fn silent_notification(&self, data: SendNotificationData, metadata: String) {
+        Self::enqueue(None, data, metadata);
+    }
+}
+
+// Records the notifications in stable memory of this canister, used when notifications are disabled
+pub struct LocalNotificationSink;
+
+impl LocalNotificationSink {
+    fn record(sender: Option<Principal>, data: SendNotificationData, metadata: String) {
+        LOCAL_NOTIFICATIONS.with(|notifications| {
+            let mut notifications = notifications.borrow_mut();
+            let id = notifications
+                .last_key_value()
+                .map(|(k, _)| k + 1)
+                .unwrap_or(0);
+            notifications.insert(
+                id,
+                LocalNotification {
+                    sender,
+                    receivers: data.receivers,
+                    notification_type: serde_json::to_string(&data.data).unwrap_or_default(),
+                    metadata,
+                    created_at: time(),
+                },
+            );
+
+            // Only the newest notifications are kept
+            while notifications.len() > LOCAL_NOTIFICATIONS_LIMIT {
+                match notifications.first_key_value() {
+                    Some((oldest, _)) => notifications.remove(&oldest),
+                    None => break,
+                };
+            }
+        });
+    }
+}
+
+impl NotificationSink for LocalNotificationSink {
+    fn add_notification(&self, sender: Principal, data: SendNotificationData, metadata: String) {
+        Self::record(Some(sender), data, metadata);
+    }
+
+    fn silent_notification(&self, data: SendNotificationData, metadata: String) {
+        Self::record(None, data, metadata);
+    }
+}
+
+// Keeps the notifications in memory so tests can assert which notifications were emitted
+#[cfg(test)]
+#[derive(Default)]
+pub struct InMemoryNotificationSink {
+    pub notifications: std::cell::RefCell<Vec<(Option<Principal>, SendNotificationData)>>,
+}
+
+#[cfg(test)]
+impl NotificationSink for InMemoryNotificationSink {
+    fn add_notification(&self, sender: Principal, data: SendNotificationData, _metadata: String) {
+        self.notifications.borrow_mut().push((Some(sender), data));
+    }
+
+    fn silent_notification(&self, data: SendNotificationData, _metadata: String) {
+        self.notifications.borrow_mut().push((None, data));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::store::{ENTRIES, FRIEND_REQUEST};
+use crate::store::{Store, ENTRIES, FRIEND_REQUEST};
 use shared::profile_models::{FriendRequest, Profile};
 
 #[ic_cdk::query(guard = "auth")]
@@ -17,17 +17,18 @@ fn read_profile_friend_requests() -> Vec<(u64, FriendRequest)> {
 }
 
 // GUARDS
-const ALLOWED: [&str; 3] = [
+// Allowed next to the admins of the store
+const ALLOWED: [&str; 2] = [
     // sam candid ui
     "nvifv-62idm-izjcy-rvy63-7tqjz-mg2d7-jiw6m-soqvp-hdayh-mnqf5-yqe",
-    // catalyze development
-    "syzio-xu6ca-burmx-4afo2-ojpcw-e75j3-m67o5-s5bes-5vvsv-du3t4-wae",
     // proxy
     "bwm3m-wyaaa-aaaag-qdiua-cai",
 ];
 
 fn auth() -> Result<(), String> {
-    if ALLOWED.contains(&ic_cdk::caller().to_string().as_str()) {
+    if ALLOWED.contains(&ic_cdk::caller().to_string().as_str())
+        || Store::get_admins().contains(&ic_cdk::caller())
+    {
         Ok(())
     } else {
         Err("Unauthorized".to_string())
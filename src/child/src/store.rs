@@ -1,10 +1,13 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
 
 use candid::Principal;
-use ic_catalyze_notifications::models::{Environment, FriendRequestNotificationData};
-use ic_catalyze_notifications::store::Notification;
-use ic_cdk::api::{call, time};
-use ic_cdk::id;
+use ic_catalyze_notifications::models::{
+    Environment, FriendRequestNotificationData, NotificationType, SendNotificationData,
+};
+use ic_cdk::api::call;
+use ic_cdk::api::management_canister::main::raw_rand;
 use ic_scalable_canister::store::Data;
 
 use ic_scalable_canister::ic_scalable_misc::helpers::serialize_helper::serialize;
@@ -16,15 +19,24 @@ use ic_scalable_canister::ic_scalable_misc::{
         asset_type::Asset,
         sort_type::SortDirection,
     },
-    helpers::{error_helper::api_error, paging_helper::get_paged_data},
+    helpers::paging_helper::get_paged_data,
     models::paged_response_models::PagedResponse,
 };
 
 use serde_json::json;
 use shared::profile_models::{
-    DocumentDetails, FriendRequest, FriendRequestResponse, PostProfile, PostWallet, Profile,
-    ProfileFilter, ProfileResponse, ProfileSort, RelationType, UpdateProfile, Wallet,
-    WalletResponse,
+    AdminConfig, Audience, ClearRelationsRequest, ClearRelationsSummary, ContactGroup,
+    ContactGroupResponse, ContactNote, ContactNoteResponse, CursorPagedRelationsResponse,
+    DocumentDetails, FieldVisibility, FriendRequest, FriendRequestLimits, FriendRequestResponse,
+    FriendRequestWithProfileResponse, FriendSuggestion, GraphConsistencyReport, LocalNotification,
+    NotificationEnvironment, NotificationPreference, NotificationPreferences, OutboxNotification,
+    OutboxNotificationResponse, PostContactGroup, PostContactNote, PostProfile, PostWallet,
+    Profile, ProfileChange, ProfileChangeResponse, ProfileDeletion, ProfileField,
+    ProfileFieldChange, ProfileFieldVisibility, ProfileFilter, ProfilePrivacy,
+    ProfileRecommendation, ProfileResponse, ProfileSort, ProfileSummary, ProfileTombstone,
+    RecommendationSource, Relation, RelationKey, RelationResponse, RelationSort, RelationType,
+    RelationWithProfileResponse, RelationsVisibility, SentFriendRequests, UpdateProfile,
+    UsernameChange, UsernameHistory, Wallet, WalletResponse,
 };
 
 use ic_stable_structures::{
@@ -33,16 +45,92 @@ use ic_stable_structures::{
 };
 
 use std::cell::RefCell;
+use std::rc::Rc;
 
+use crate::notification_sink::{LocalNotificationSink, NotificationSink, OutboxNotificationSink};
+use crate::system_api::{api_error, default_data, get_canisters, id, is_controller, time};
 use crate::IDENTIFIER_KIND;
 
-use super::validation::{validate_post_profile, validate_update_profile};
+use super::validation::{
+    validate_post_contact_group, validate_post_contact_note, validate_post_profile,
+    validate_update_profile, validate_username,
+};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
 pub static DATA_MEMORY_ID: MemoryId = MemoryId::new(0);
 pub static ENTRIES_MEMORY_ID: MemoryId = MemoryId::new(1);
 pub static FRIEND_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub static USERNAME_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub static PROFILE_DELETIONS_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub static TOMBSTONES_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub static PROFILE_CHANGES_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub static RELATIONS_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub static FOLLOWING_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub static FOLLOWERS_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub static MUTES_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub static FRIEND_REQUEST_TTL_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub static FRIEND_REQUEST_LIMITS_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub static RELATIONS_VISIBILITY_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub static CONTACT_GROUPS_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub static FIELD_VISIBILITY_MEMORY_ID: MemoryId = MemoryId::new(15);
+pub static CONTACT_NOTES_MEMORY_ID: MemoryId = MemoryId::new(16);
+pub static NOTIFICATION_ENVIRONMENT_MEMORY_ID: MemoryId = MemoryId::new(17);
+pub static LOCAL_NOTIFICATIONS_MEMORY_ID: MemoryId = MemoryId::new(18);
+pub static NOTIFICATION_OUTBOX_MEMORY_ID: MemoryId = MemoryId::new(19);
+pub static NOTIFICATION_DEAD_LETTERS_MEMORY_ID: MemoryId = MemoryId::new(20);
+pub static USERNAME_HISTORY_BY_PROFILE_MEMORY_ID: MemoryId = MemoryId::new(21);
+pub static ADMINS_MEMORY_ID: MemoryId = MemoryId::new(22);
+pub static FRIEND_REQUESTS_SENT_MEMORY_ID: MemoryId = MemoryId::new(23);
+pub static NOTIFICATION_OUTBOX_NEXT_ID_MEMORY_ID: MemoryId = MemoryId::new(24);
+pub static LEGACY_RELATIONS_MIGRATED_ON_MEMORY_ID: MemoryId = MemoryId::new(25);
+pub static CONTACT_GROUPS_NEXT_ID_MEMORY_ID: MemoryId = MemoryId::new(26);
+
+// Minimum time between two username changes of the same profile (30 days)
+pub static USERNAME_CHANGE_COOLDOWN: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+// Time an old username stays reserved for its previous owner (90 days)
+pub static USERNAME_RESERVATION_PERIOD: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+// Version of the document returned by the personal data export
+pub static DATA_EXPORT_VERSION: u64 = 2;
+// The `exported_on` of the personal data export is rounded down to this window (5 minutes),
+// so chunks fetched within the same window belong to the same document as long as the data did not change
+pub static DATA_EXPORT_WINDOW: u64 = 5 * 60 * 1_000_000_000;
+// Time the profile changes are kept in the change log (365 days)
+pub static PROFILE_CHANGE_RETENTION: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+// Time the values of sensitive fields are kept in the change log before they are redacted (30 days)
+pub static SENSITIVE_PROFILE_CHANGE_RETENTION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+// Fields of which the old and new values are redacted from the change log after the sensitive retention period
+pub static SENSITIVE_PROFILE_FIELDS: [&str; 2] = ["email", "date_of_birth"];
+// Value of a field change that is redacted from the change log
+pub static REDACTED_PROFILE_FIELD_VALUE: &str = "\"[redacted]\"";
+// Number of profiles that are re-encoded per batch by the profile migration job
+pub static PROFILE_MIGRATION_BATCH_SIZE: usize = 100;
+// Time a scheduled profile deletion can still be cancelled (14 days)
+pub static PROFILE_DELETION_GRACE_PERIOD: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+// Time a nonce to confirm clearing the relations is valid (5 minutes)
+pub static CLEAR_RELATIONS_NONCE_TTL: u64 = 5 * 60 * 1_000_000_000;
+// Code that had to be passed to the deprecated `clear_relations`
+pub static CLEAR_RELATIONS_CODE: &str = "i_know_what_i_am_doing";
+// The class of opaque principals, the last byte of the principal of a canister
+pub static OPAQUE_PRINCIPAL_CLASS: u8 = 0x01;
+// Weights of the parts of the recommendation score, together they add up to 1
+pub static RECOMMENDATION_SKILLS_WEIGHT: f64 = 0.3;
+pub static RECOMMENDATION_INTERESTS_WEIGHT: f64 = 0.3;
+pub static RECOMMENDATION_CAUSES_WEIGHT: f64 = 0.2;
+pub static RECOMMENDATION_COUNTRY_WEIGHT: f64 = 0.1;
+pub static RECOMMENDATION_STATE_WEIGHT: f64 = 0.1;
+// Default time a friend request stays open before it expires (30 days)
+pub static DEFAULT_FRIEND_REQUEST_TTL: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+pub static HOUR_IN_NANOS: u64 = 60 * 60 * 1_000_000_000;
+pub static DAY_IN_NANOS: u64 = 24 * HOUR_IN_NANOS;
+// Number of notifications that are kept when they are recorded locally, the oldest are removed first
+pub static LOCAL_NOTIFICATIONS_LIMIT: u64 = 1000;
+// Delay before the first retry of a failed notification, doubled on every next attempt (30 seconds)
+pub static NOTIFICATION_RETRY_BASE_DELAY: u64 = 30 * 1_000_000_000;
+// Number of delivery attempts before a notification is moved to the dead letters
+pub static NOTIFICATION_MAX_ATTEMPTS: u32 = 8;
+// Number of notifications that are delivered per run of the outbox job
+pub static NOTIFICATION_OUTBOX_BATCH_SIZE: usize = 50;
 
 thread_local! {
     pub static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
@@ -51,7 +139,7 @@ thread_local! {
         pub static STABLE_DATA: RefCell<StableCell<Data, Memory>> = RefCell::new(
             StableCell::init(
                 MEMORY_MANAGER.with(|m| m.borrow().get(DATA_MEMORY_ID)),
-                Data::default(),
+                default_data(),
             ).expect("failed")
         );
 
@@ -66,8 +154,205 @@ thread_local! {
                 MEMORY_MANAGER.with(|m| m.borrow().get(FRIEND_REQUESTS_MEMORY_ID)),
             )
         );
+
+        // Identifier of the last profile that was re-encoded by the profile migration job
+        pub static PROFILE_MIGRATION_CURSOR: RefCell<Option<String>> = const { RefCell::new(None) };
+
+        // Time the profile migration job moved the relations of all profiles from the deprecated string map
+        // of the profile to the relations store, `0` until then for canisters that were installed before the
+        // relations store existed
+        pub static LEGACY_RELATIONS_MIGRATED_ON: RefCell<StableCell<u64, Memory>> = RefCell::new(
+            StableCell::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(LEGACY_RELATIONS_MIGRATED_ON_MEMORY_ID)),
+                0,
+            ).expect("failed")
+        );
+
+        // Old usernames, keyed by the username, with the principal that used it
+        pub static USERNAME_HISTORY: RefCell<StableBTreeMap<String, UsernameChange, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(USERNAME_HISTORY_MEMORY_ID)),
+            )
+        );
+
+        // Old usernames per profile, keyed by the principal of the profile owner
+        pub static USERNAME_HISTORY_BY_PROFILE: RefCell<StableBTreeMap<String, UsernameHistory, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(USERNAME_HISTORY_BY_PROFILE_MEMORY_ID)),
+            )
+        );
+
+        // Scheduled profile deletions, keyed by the principal of the profile owner
+        pub static PROFILE_DELETIONS: RefCell<StableBTreeMap<String, ProfileDeletion, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(PROFILE_DELETIONS_MEMORY_ID)),
+            )
+        );
+
+        // Append-only log of the changes made to profiles
+        pub static PROFILE_CHANGES: RefCell<StableBTreeMap<u64, ProfileChange, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(PROFILE_CHANGES_MEMORY_ID)),
+            )
+        );
+
+        // Tombstones of deleted profiles, keyed by the identifier of the deleted profile
+        pub static TOMBSTONES: RefCell<StableBTreeMap<String, ProfileTombstone, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(TOMBSTONES_MEMORY_ID)),
+            )
+        );
+
+        // Relations between principals, keyed by the owner and the other principal of the relation
+        pub static RELATIONS: RefCell<StableBTreeMap<RelationKey, Relation, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(RELATIONS_MEMORY_ID)),
+            )
+        );
+
+        // Follows, keyed by the follower and the followed principal
+        pub static FOLLOWING: RefCell<StableBTreeMap<RelationKey, Relation, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(FOLLOWING_MEMORY_ID)),
+            )
+        );
+
+        // The same follows as `FOLLOWING`, keyed by the followed principal and the follower
+        pub static FOLLOWERS: RefCell<StableBTreeMap<RelationKey, Relation, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(FOLLOWERS_MEMORY_ID)),
+            )
+        );
+
+        // Time a friend request stays open before it expires, can be changed by an admin
+        pub static FRIEND_REQUEST_TTL: RefCell<StableCell<u64, Memory>> = RefCell::new(
+            StableCell::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(FRIEND_REQUEST_TTL_MEMORY_ID)),
+                DEFAULT_FRIEND_REQUEST_TTL,
+            ).expect("failed")
+        );
+
+        // Principals that can use the admin methods, can be changed by the controllers
+        pub static ADMINS: RefCell<StableCell<AdminConfig, Memory>> = RefCell::new(
+            StableCell::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(ADMINS_MEMORY_ID)),
+                AdminConfig {
+                    admins: Principal::from_text(DEFAULT_ADMIN).into_iter().collect(),
+                },
+            ).expect("failed")
+        );
+
+        // Rate limits for sending friend requests, can be changed by an admin
+        pub static FRIEND_REQUEST_LIMITS: RefCell<StableCell<FriendRequestLimits, Memory>> = RefCell::new(
+            StableCell::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(FRIEND_REQUEST_LIMITS_MEMORY_ID)),
+                FriendRequestLimits::default(),
+            ).expect("failed")
+        );
+
+        // Times the friend requests were sent in the last day, keyed by the principal of the requester, only used for rate limiting
+        pub static FRIEND_REQUESTS_SENT: RefCell<StableBTreeMap<String, SentFriendRequests, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(FRIEND_REQUESTS_SENT_MEMORY_ID)),
+            )
+        );
+
+        // Who can see the relations of a profile, keyed by the principal of the profile owner, not set means public
+        pub static RELATIONS_VISIBILITY: RefCell<StableBTreeMap<String, RelationsVisibility, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(RELATIONS_VISIBILITY_MEMORY_ID)),
+            )
+        );
+
+        // Contact groups of all profiles, keyed by the id of the group
+        pub static CONTACT_GROUPS: RefCell<StableBTreeMap<u64, ContactGroup, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(CONTACT_GROUPS_MEMORY_ID)),
+            )
+        );
+
+        // Id of the next contact group, ids are not reused so a field is never visible to a later group
+        pub static CONTACT_GROUPS_NEXT_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+            StableCell::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(CONTACT_GROUPS_NEXT_ID_MEMORY_ID)),
+                0,
+            ).expect("failed")
+        );
+
+        // Who can see the fields of a profile, keyed by the principal of the profile owner
+        pub static FIELD_VISIBILITY: RefCell<StableBTreeMap<String, ProfileFieldVisibility, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(FIELD_VISIBILITY_MEMORY_ID)),
+            )
+        );
+
+        // Private notes about relations, keyed by the owner of the note and the principal it is about
+        pub static CONTACT_NOTES: RefCell<StableBTreeMap<RelationKey, ContactNote, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(CONTACT_NOTES_MEMORY_ID)),
+            )
+        );
+
+        // Where the notifications are send to, can be changed by an admin
+        pub static NOTIFICATION_ENVIRONMENT: RefCell<StableCell<NotificationEnvironment, Memory>> = RefCell::new(
+            StableCell::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(NOTIFICATION_ENVIRONMENT_MEMORY_ID)),
+                NotificationEnvironment::default(),
+            ).expect("failed")
+        );
+
+        // Notifications that are recorded instead of send, when there is no notification environment
+        pub static LOCAL_NOTIFICATIONS: RefCell<StableBTreeMap<u64, LocalNotification, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(LOCAL_NOTIFICATIONS_MEMORY_ID)),
+            )
+        );
+
+        // Notifications that are written together with the state change and delivered by a timer
+        pub static NOTIFICATION_OUTBOX: RefCell<StableBTreeMap<u64, OutboxNotification, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(NOTIFICATION_OUTBOX_MEMORY_ID)),
+            )
+        );
+
+        // Id of the next notification in the outbox, ids are not reused so a dead letter keeps a unique id
+        pub static NOTIFICATION_OUTBOX_NEXT_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+            StableCell::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(NOTIFICATION_OUTBOX_NEXT_ID_MEMORY_ID)),
+                0,
+            ).expect("failed")
+        );
+
+        // Notifications of which all delivery attempts failed, keyed by the id they had in the outbox
+        pub static NOTIFICATION_DEAD_LETTERS: RefCell<StableBTreeMap<u64, OutboxNotification, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(NOTIFICATION_DEAD_LETTERS_MEMORY_ID)),
+            )
+        );
+
+        // Replaces the sink of the notification environment when set, for example with a recorder in tests
+        pub static NOTIFICATION_SINK: RefCell<Option<Rc<dyn NotificationSink>>> = RefCell::new(None);
+
+        // Nonces to confirm clearing the relations, with the time they expire, per principal
+        pub static CLEAR_RELATIONS_NONCES: RefCell<HashMap<Principal, (String, u64)>> = RefCell::new(HashMap::new());
+
+        // Report of the last social graph consistency check
+        pub static GRAPH_CONSISTENCY_REPORT: RefCell<Option<GraphConsistencyReport>> = const { RefCell::new(None) };
+
+        // Mutes, keyed by the principal that muted and the muted principal
+        pub static MUTES: RefCell<StableBTreeMap<RelationKey, Relation, Memory>> = RefCell::new(
+            StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MUTES_MEMORY_ID)),
+            )
+        );
 }
 
+// Principals that are allowed to use the admin methods, next to the controllers of this canister
+// The admin of a new canister, admins can be changed by the controllers afterwards
+const DEFAULT_ADMIN: &str =
+    // catalyze development
+    "syzio-xu6ca-burmx-4afo2-ojpcw-e75j3-m67o5-s5bes-5vvsv-du3t4-wae";
+
 pub struct Store;
 
 impl Store {
@@ -103,7 +388,8 @@ impl Store {
                     if Self::_has_user_name(
                         &ENTRIES.with(|entries| Data::get_entries(entries)),
                         &post_profile.username,
-                    ) {
+                    ) || Self::_is_username_reserved(&post_profile.username, caller)
+                    {
                         return Err(api_error(
                             ApiErrorType::BadRequest,
                             "USERNAME_TAKEN",
@@ -147,6 +433,7 @@ impl Store {
                             approved_date: 0,
                         },
                         extra: post_profile.extra,
+                        notification_preferences: NotificationPreferences::default(),
                         updated_on: time(),
                         created_on: time(),
                         member_identifier: Principal::anonymous(),
@@ -180,6 +467,10 @@ impl Store {
                             _ => Err(err),
                         },
                         Ok((identifier, mut profile)) => {
+                            // A new profile of a principal that deleted its profile before is no longer deleted
+                            TOMBSTONES.with(|tombstones| {
+                                tombstones.borrow_mut().remove(&caller.to_string())
+                            });
                             // Create a new member entry on the specified member canister
                             let member_result: Result<(Result<Principal, ApiError>,), _> =
                                 call::call(
@@ -192,17 +483,13 @@ impl Store {
                                 Ok(_result) => match _result.0 {
                                     Ok(_member_identifier) => {
                                         // Update the profile with the member identifier
-                                        STABLE_DATA.with(|data| {
-                                            profile.member_identifier = _member_identifier;
-                                            let _ = ENTRIES.with(|entries| {
-                                                Data::update_entry(
-                                                    data,
-                                                    entries,
-                                                    identifier,
-                                                    profile.clone(),
-                                                )
-                                            });
-                                        });
+                                        profile.member_identifier = _member_identifier;
+                                        let _ = Self::_update_entry(
+                                            caller,
+                                            "add_profile",
+                                            identifier,
+                                            profile.clone(),
+                                        );
                                         Ok(Self::_map_profile_to_profile_response(
                                             identifier.to_string(),
                                             profile,
@@ -292,11 +579,7 @@ impl Store {
                         profile.updated_on = time();
 
                         // update the profile in the data store
-                        match STABLE_DATA.with(|data| {
-                            ENTRIES.with(|entries| {
-                                Data::update_entry(data, entries, _identifier, profile)
-                            })
-                        }) {
+                        match Self::_update_entry(caller, "update_profile", _identifier, profile) {
                             Err(err) => Err(err),
                             Ok((identifier, profile)) => {
                                 Ok(Self::_map_profile_to_profile_response(
@@ -311,6 +594,157 @@ impl Store {
         }
     }
 
+    // Method to change the username of a profile, the old username stays reserved for a grace period
+    pub fn change_username(
+        caller: Principal,
+        username: String,
+    ) -> Result<ProfileResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller),
+            format!("username - {:?}", &username),
+        ]);
+
+        // get the profile from the data store
+        match Self::_get_profile_from_caller(caller) {
+            // If the profile does not exist, return an error
+            None => Err(Self::_profile_not_found_error("change_username", inputs)),
+            // If the profile exists, continue and validate the username
+            Some((_identifier, mut _profile)) => {
+                validate_username(username.clone())?;
+
+                if _profile.username == username {
+                    return Err(api_error(
+                        ApiErrorType::BadRequest,
+                        "SAME_USERNAME",
+                        "The new username is the same as the current username",
+                        STABLE_DATA
+                            .with(|data| Data::get_name(data.borrow().get()))
+                            .as_str(),
+                        "change_username",
+                        inputs,
+                    ));
+                }
+
+                // Check if the username is used by another profile or still reserved for its previous owner
+                if Self::_has_user_name(&ENTRIES.with(Data::get_entries), &username)
+                    || Self::_is_username_reserved(&username, caller)
+                {
+                    return Err(api_error(
+                        ApiErrorType::BadRequest,
+                        "USERNAME_TAKEN",
+                        "Username already taken",
+                        STABLE_DATA
+                            .with(|data| Data::get_name(data.borrow().get()))
+                            .as_str(),
+                        "change_username",
+                        inputs,
+                    ));
+                }
+
+                // Check if the last username change is longer ago than the cooldown
+                let last_change = USERNAME_HISTORY_BY_PROFILE
+                    .with(|history| history.borrow().get(&caller.to_string()));
+
+                if let Some(_last_change) = last_change {
+                    if _last_change.last_changed_on + USERNAME_CHANGE_COOLDOWN > time() {
+                        return Err(api_error(
+                            ApiErrorType::BadRequest,
+                            "USERNAME_CHANGE_COOLDOWN",
+                            "The username was changed too recently",
+                            STABLE_DATA
+                                .with(|data| Data::get_name(data.borrow().get()))
+                                .as_str(),
+                            "change_username",
+                            inputs,
+                        ));
+                    }
+                }
+
+                // Reserve the old username and release the new one if the caller used it before
+                USERNAME_HISTORY.with(|history| {
+                    let mut history = history.borrow_mut();
+                    history.remove(&username);
+                    history.insert(
+                        _profile.username.clone(),
+                        UsernameChange {
+                            principal: caller,
+                            changed_on: time(),
+                        },
+                    );
+                });
+                USERNAME_HISTORY_BY_PROFILE.with(|history| {
+                    let mut history = history.borrow_mut();
+                    let mut _history = history.get(&caller.to_string()).unwrap_or_default();
+                    _history
+                        .usernames
+                        .retain(|_username| _username != &username);
+                    _history.usernames.push(_profile.username.clone());
+                    _history.last_changed_on = time();
+                    history.insert(caller.to_string(), _history);
+                });
+
+                _profile.username = username;
+                _profile.updated_on = time();
+
+                // Update the profile in the data store
+                Self::_update_entry(caller, "change_username", _identifier, _profile).map_or_else(
+                    Err,
+                    |result| {
+                        Ok(Self::_map_profile_to_profile_response(
+                            result.0.to_string(),
+                            result.1,
+                        ))
+                    },
+                )
+            }
+        }
+    }
+
+    // Method to get a profile by its current username, or by a previous username of the profile
+    pub fn resolve_username(
+        caller: Principal,
+        username: String,
+    ) -> Result<ProfileResponse, ApiError> {
+        let inputs = Some(vec![format!("username - {:?}", &username)]);
+
+        let profiles = ENTRIES.with(Data::get_entries);
+
+        // A profile that currently uses the username always takes precedence
+        if let Some((_identifier, _profile)) = profiles
+            .iter()
+            .find(|(_, _profile)| _profile.username == username)
+        {
+            if Self::_is_blocked_by(_profile.principal, caller) {
+                return Err(Self::_profile_not_found_error("resolve_username", inputs));
+            }
+            return Ok(Self::_map_profile_to_visible_profile_response(
+                caller,
+                _identifier.clone(),
+                _profile.clone(),
+            ));
+        }
+
+        // Follow the old username to the profile that used it, as long as it is reserved for that profile
+        match USERNAME_HISTORY.with(|history| history.borrow().get(&username)) {
+            None => Err(Self::_profile_not_found_error("resolve_username", inputs)),
+            Some(_change) if Self::_is_deleted(_change.principal) => {
+                Err(Self::_profile_deleted_error("resolve_username", inputs))
+            }
+            Some(_change) if _change.changed_on + USERNAME_RESERVATION_PERIOD <= time() => {
+                Err(Self::_profile_not_found_error("resolve_username", inputs))
+            }
+            Some(_change) => match profiles.into_iter().find(|(_, _profile)| {
+                _profile.principal == _change.principal
+                    && !Self::_is_blocked_by(_profile.principal, caller)
+            }) {
+                None => Err(Self::_profile_not_found_error("resolve_username", inputs)),
+                Some((_identifier, _profile)) => Ok(
+                    Self::_map_profile_to_visible_profile_response(caller, _identifier, _profile),
+                ),
+            },
+        }
+    }
+
     pub fn add_wallet(caller: Principal, wallet: PostWallet) -> Result<ProfileResponse, ApiError> {
         let inputs = Some(vec![
             format!("principal - {:?}", &caller),
@@ -332,55 +766,62 @@ impl Store {
                     },
                 );
                 // Update the profile in the data store
-                STABLE_DATA
-                    .with(|data| {
-                        ENTRIES.with(|entries| {
-                            Data::update_entry(data, entries, _identifier, _profile)
-                        })
-                    })
-                    .map_or_else(
-                        |err| Err(err),
-                        |result| {
-                            Ok(Self::_map_profile_to_profile_response(
-                                result.0.to_string(),
-                                result.1,
-                            ))
-                        },
-                    )
+                Self::_update_entry(caller, "add_wallet", _identifier, _profile).map_or_else(
+                    |err| Err(err),
+                    |result| {
+                        Ok(Self::_map_profile_to_profile_response(
+                            result.0.to_string(),
+                            result.1,
+                        ))
+                    },
+                )
             }
         }
     }
 
     // Method to set a wallet as primary
-    pub fn set_wallet_as_primary(caller: Principal, wallet_principal: Principal) -> Result<(), ()> {
+    pub fn set_wallet_as_primary(
+        caller: Principal,
+        wallet_principal: Principal,
+    ) -> Result<ProfileResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller),
+            format!("wallet_principal - {:?}", &wallet_principal),
+        ]);
+
         // get the profile from the data store
-        if let Some((_identifier, mut _profile)) = Store::_get_profile_from_caller(caller) {
-            // Check if the wallet exists
-            if _profile.wallets.get(&wallet_principal).is_none() {
-                return Err(());
-            }
+        match Self::_get_profile_from_caller(caller) {
+            // If the profile does not exist, return an error
+            None => Err(Self::_profile_not_found_error(
+                "set_wallet_as_primary",
+                inputs,
+            )),
+            // If the profile exists, continue
+            Some((_identifier, mut _profile)) => {
+                // Check if the wallet exists
+                if !_profile.wallets.contains_key(&wallet_principal) {
+                    return Err(api_error(
+                        ApiErrorType::NotFound,
+                        "WALLET_NOT_FOUND",
+                        "Wallet not found",
+                        STABLE_DATA
+                            .with(|data| Data::get_name(data.borrow().get()))
+                            .as_str(),
+                        "set_wallet_as_primary",
+                        inputs,
+                    ));
+                }
 
-            // Set all wallets to not primary
-            for (_wallet_principal, mut _wallet) in _profile.wallets.iter_mut() {
-                _wallet.is_primary = false;
-            }
-            // Set the wallet as primary
-            _profile
-                .wallets
-                .get_mut(&wallet_principal)
-                .unwrap()
-                .is_primary = true;
+                // Only the given wallet is primary
+                for (_wallet_principal, _wallet) in _profile.wallets.iter_mut() {
+                    _wallet.is_primary = _wallet_principal == &wallet_principal;
+                }
 
-            // Update the profile in the data store
-            if let Ok(_) = STABLE_DATA.with(|data| {
-                ENTRIES.with(|entries| Data::update_entry(data, entries, _identifier, _profile))
-            }) {
-                return Ok(());
-            } else {
-                return Err(());
+                // Update the profile in the data store
+                Self::_update_entry(caller, "set_wallet_as_primary", _identifier, _profile).map(
+                    |result| Self::_map_profile_to_profile_response(result.0.to_string(), result.1),
+                )
             }
-        } else {
-            return Err(());
         }
     }
 
@@ -417,21 +858,15 @@ impl Store {
                 _profile.wallets.remove(&wallet_principal);
 
                 // Update the profile in the data store
-                STABLE_DATA
-                    .with(|data| {
-                        ENTRIES.with(|entries| {
-                            Data::update_entry(data, entries, _identifier, _profile)
-                        })
-                    })
-                    .map_or_else(
-                        |err| Err(err),
-                        |result| {
-                            Ok(Self::_map_profile_to_profile_response(
-                                result.0.to_string(),
-                                result.1,
-                            ))
-                        },
-                    )
+                Self::_update_entry(caller, "remove_wallet", _identifier, _profile).map_or_else(
+                    |err| Err(err),
+                    |result| {
+                        Ok(Self::_map_profile_to_profile_response(
+                            result.0.to_string(),
+                            result.1,
+                        ))
+                    },
+                )
             }
         }
     }
@@ -469,21 +904,15 @@ impl Store {
                 // Add the starred identifier to the profile
                 _profile.starred.insert(starred_identifier, kind);
 
-                STABLE_DATA
-                    .with(|data| {
-                        ENTRIES.with(|entries| {
-                            Data::update_entry(data, entries, _identifier, _profile)
-                        })
-                    })
-                    .map_or_else(
-                        |err| Err(err),
-                        |result| {
-                            Ok(Self::_map_profile_to_profile_response(
-                                result.0.to_string(),
-                                result.1,
-                            ))
-                        },
-                    )
+                Self::_update_entry(caller, "add_starred", _identifier, _profile).map_or_else(
+                    |err| Err(err),
+                    |result| {
+                        Ok(Self::_map_profile_to_profile_response(
+                            result.0.to_string(),
+                            result.1,
+                        ))
+                    },
+                )
             }
         }
     }
@@ -521,21 +950,15 @@ impl Store {
                 // Remove the starred identifier from the profile
                 _profile.starred.remove(&starred_identifier);
                 // Update the profile in the data store
-                STABLE_DATA
-                    .with(|data| {
-                        ENTRIES.with(|entries| {
-                            Data::update_entry(data, entries, _identifier, _profile)
-                        })
-                    })
-                    .map_or_else(
-                        |err| Err(err),
-                        |result| {
-                            Ok(Self::_map_profile_to_profile_response(
-                                result.0.to_string(),
-                                result.1,
-                            ))
-                        },
-                    )
+                Self::_update_entry(caller, "remove_starred", _identifier, _profile).map_or_else(
+                    |err| Err(err),
+                    |result| {
+                        Ok(Self::_map_profile_to_profile_response(
+                            result.0.to_string(),
+                            result.1,
+                        ))
+                    },
+                )
             }
         }
     }
@@ -599,93 +1022,500 @@ impl Store {
             // If the profile does not exist, return an error
             None => Err(Self::_profile_not_found_error("add_relation", inputs)),
             // If the profile exists, continue
-            Some((_identifier, mut _profile)) => {
-                // Add the relation to the profile, if existing it will be overwritten
-                _profile
-                    .relations
-                    .insert(relation_identifier, relation_type.to_string());
-
-                // Update the profile in the data store
-                STABLE_DATA
-                    .with(|data| {
-                        ENTRIES.with(|entries| {
-                            Data::update_entry(data, entries, _identifier, _profile)
-                        })
-                    })
-                    .map_or_else(
-                        |err| Err(err),
-                        |result| {
-                            Ok(Self::_map_profile_to_profile_response(
-                                result.0.to_string(),
-                                result.1,
-                            ))
-                        },
-                    )
+            Some((_identifier, _profile)) => {
+                // Add the relation, if existing it will be overwritten
+                Self::_set_relation(
+                    caller,
+                    relation_identifier,
+                    relation_type,
+                    caller,
+                    caller,
+                    "add_relation",
+                );
+                Ok(Self::_map_profile_to_profile_response(
+                    _identifier.to_string(),
+                    _profile,
+                ))
             }
         }
     }
 
     // Method to get the relations of a profile by type
     pub fn get_relations(caller: Principal, relation_type: RelationType) -> Vec<Principal> {
-        // Create a vector to hold the relations
-        let mut relations = vec![];
-
-        // get the profile from the data store
-        let profile = Self::_get_profile_from_caller(caller);
-        // If the profile exists, continue
-        if let Some((_principal, _profile)) = profile {
-            // Iterate through the relations in the profile
-            _profile
-                .relations
-                .into_iter()
-                .for_each(|(_relation_identifier, _relation_type)| {
-                    // If the relation type matches the relation type passed in, add it to the vector
-                    if _relation_type == relation_type.to_string() {
-                        relations.push(_relation_identifier);
-                    }
-                });
-            return relations;
-        };
-        return vec![];
+        Self::_get_relations_by_type(caller, relation_type)
+            .into_iter()
+            .map(|(other, _)| other)
+            .collect()
     }
 
-    // Method to get the profile of the caller
+    // Method to get the friends the caller has in common with another principal, only when the caller can see the friends of the other principal
+    pub fn get_mutual_friends(caller: Principal, other: Principal) -> Vec<Principal> {
+        if !Self::_can_view_relations(caller, other, &RelationType::Friend) {
+            return vec![];
+        }
+
+        let other_friends = Self::get_relations(other, RelationType::Friend);
+        Self::get_relations(caller, RelationType::Friend)
+            .into_iter()
+            .filter(|friend| other_friends.contains(friend))
+            .collect()
+    }
+
+    // Method to get friends of friends of the caller, ranked by the number of mutual friends,
+    // only the friends of friends that allow the caller to see their friends are used
+    pub fn get_friend_suggestions(caller: Principal, limit: usize) -> Vec<FriendSuggestion> {
+        let friends = Self::get_relations(caller, RelationType::Friend);
+        let visible_friends: Vec<Principal> = friends
+            .iter()
+            .filter(|friend| Self::_can_view_relations(caller, **friend, &RelationType::Friend))
+            .cloned()
+            .collect();
+
+        let mut suggestions: Vec<FriendSuggestion> = Self::_count_second_degree(&visible_friends)
+            .into_iter()
+            .filter(|(principal, _)| {
+                principal != &caller
+                    && !friends.contains(principal)
+                    && !Self::_is_blocked_by(caller, *principal)
+                    && !Self::_is_blocked_by(*principal, caller)
+                    && !Self::_is_muted_by(caller, *principal)
+                    && !Self::_has_pending_friend_request(caller, *principal)
+            })
+            .map(|(principal, mutual_friends)| FriendSuggestion {
+                principal,
+                mutual_friends,
+            })
+            .collect();
+
+        suggestions.sort_by(|a, b| {
+            b.mutual_friends
+                .cmp(&a.mutual_friends)
+                .then(a.principal.cmp(&b.principal))
+        });
+        suggestions.truncate(limit);
+        suggestions
+    }
+
+    // Method to get other public profiles ranked by the overlap of their taxonomy and location with the caller
+    pub fn recommend_profiles(
+        caller: Principal,
+        limit: usize,
+    ) -> Result<Vec<ProfileRecommendation>, ApiError> {
+        match Self::get_recommendation_source(caller) {
+            None => Err(Self::_profile_not_found_error(
+                "recommend_profiles",
+                Some(vec![format!("limit - {:?}", &limit)]),
+            )),
+            Some(source) => Ok(Self::get_recommended_profiles(source, limit)),
+        }
+    }
+
+    // Method to get what the profiles of a principal are scored against, used by the parent canister
+    // to score the profiles of all child canisters against the same source
+    pub fn get_recommendation_source(principal: Principal) -> Option<RecommendationSource> {
+        let (_, profile) = Self::_get_profile_from_caller(principal)?;
+
+        let mut excluded = Self::get_relations(principal, RelationType::Friend);
+        excluded.append(&mut Self::get_relations(principal, RelationType::Blocked));
+        excluded.append(&mut Self::get_relations(principal, RelationType::Muted));
+
+        Some(RecommendationSource {
+            principal,
+            skills: profile.skills,
+            interests: profile.interests,
+            causes: profile.causes,
+            country: profile.country,
+            state_or_province: profile.state_or_province,
+            excluded,
+        })
+    }
+
+    // Method to score the public profiles on this canister against a recommendation source
+    pub fn get_recommended_profiles(
+        source: RecommendationSource,
+        limit: usize,
+    ) -> Vec<ProfileRecommendation> {
+        let mut recommendations: Vec<ProfileRecommendation> = ENTRIES
+            .with(Data::get_entries)
+            .into_iter()
+            .filter(|(_, _profile)| {
+                _profile.privacy == ProfilePrivacy::Public
+                    && _profile.principal != source.principal
+                    && !source.excluded.contains(&_profile.principal)
+                    && !Self::_is_blocked_by(_profile.principal, source.principal)
+            })
+            .filter_map(|(_identifier, mut _profile)| {
+                // The location only counts when the source is allowed to see it
+                let owner = _profile.principal;
+                if !Self::_is_field_visible(owner, source.principal, ProfileField::Country) {
+                    _profile.country = String::default();
+                }
+                if !Self::_is_field_visible(owner, source.principal, ProfileField::StateOrProvince)
+                {
+                    _profile.state_or_province = String::default();
+                }
+                let score = Self::_get_recommendation_score(&source, &_profile);
+                match score > 0.0 {
+                    true => Some(ProfileRecommendation {
+                        profile: Self::_map_profile_to_profile_summary(&_identifier, &_profile),
+                        score,
+                    }),
+                    false => None,
+                }
+            })
+            .collect();
+
+        recommendations.sort_by(|a, b| b.score.total_cmp(&a.score));
+        recommendations.truncate(limit);
+        recommendations
+    }
+
+    // Method to calculate the weighted overlap between the source and a profile
+    fn _get_recommendation_score(source: &RecommendationSource, profile: &Profile) -> f64 {
+        // Jaccard index, the size of the intersection divided by the size of the union
+        let jaccard = |a: &[u32], b: &[u32]| {
+            let union = a.iter().chain(b.iter()).collect::<HashSet<&u32>>().len();
+            match union {
+                0 => 0.0,
+                _ => {
+                    a.iter()
+                        .filter(|id| b.contains(id))
+                        .collect::<HashSet<&u32>>()
+                        .len() as f64
+                        / union as f64
+                }
+            }
+        };
+
+        let same_country = !source.country.is_empty()
+            && source.country.to_lowercase() == profile.country.to_lowercase();
+        let same_state = same_country
+            && !source.state_or_province.is_empty()
+            && source.state_or_province.to_lowercase() == profile.state_or_province.to_lowercase();
+
+        RECOMMENDATION_SKILLS_WEIGHT * jaccard(&source.skills, &profile.skills)
+            + RECOMMENDATION_INTERESTS_WEIGHT * jaccard(&source.interests, &profile.interests)
+            + RECOMMENDATION_CAUSES_WEIGHT * jaccard(&source.causes, &profile.causes)
+            + if same_country {
+                RECOMMENDATION_COUNTRY_WEIGHT
+            } else {
+                0.0
+            }
+            + if same_state {
+                RECOMMENDATION_STATE_WEIGHT
+            } else {
+                0.0
+            }
+    }
+
+    // Method to count how many of the passed friends are friends with each principal on this canister,
+    // the counts of multiple canisters can be summed to get suggestions across canisters
+    fn _count_second_degree(friends: &[Principal]) -> HashMap<Principal, u64> {
+        let mut counts: HashMap<Principal, u64> = HashMap::new();
+        for friend in friends {
+            for second_degree in Self::get_relations(*friend, RelationType::Friend) {
+                *counts.entry(second_degree).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    // Method to check if there is an open friend request between two principals, in either direction
+    fn _has_pending_friend_request(principal: Principal, other: Principal) -> bool {
+        FRIEND_REQUEST.with(|requests| {
+            requests.borrow().iter().any(|(_, request)| {
+                Self::_is_friend_request_open(&request)
+                    && ((request.requested_by == principal && request.to == other)
+                        || (request.requested_by == other && request.to == principal))
+            })
+        })
+    }
+
+    // Method to get the relations count of a principal by type, only counted when the caller can see the relations
+    pub fn get_relations_count(
+        caller: Principal,
+        principal: Principal,
+        relation_type: RelationType,
+    ) -> Result<u64, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &principal.to_string()),
+            format!("relation_type - {:?}", &relation_type.to_string()),
+        ]);
+
+        if Self::_get_profile_from_caller(principal).is_none() {
+            return Err(Self::_profile_not_found_error(
+                "get_relations_count",
+                inputs,
+            ));
+        }
+
+        if !Self::_can_view_relations(caller, principal, &relation_type) {
+            return Err(api_error(
+                ApiErrorType::Unauthorized,
+                "RELATIONS_HIDDEN",
+                "The relations of this profile are not visible to you",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "get_relations_count",
+                inputs,
+            ));
+        }
+
+        Ok(Self::_get_relations_by_type(principal, relation_type).len() as u64)
+    }
+
+    // Method to get the relations of any principal by type with a summary of the related profiles,
+    // relations of which the profile does not exist (anymore) are left out
+    pub fn get_relations_paged(
+        caller: Principal,
+        principal: Principal,
+        relation_type: RelationType,
+        limit: usize,
+        cursor: Option<Principal>,
+        sort: RelationSort,
+    ) -> Result<CursorPagedRelationsResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &principal.to_string()),
+            format!("relation_type - {:?}", &relation_type.to_string()),
+            format!("cursor - {:?}", &cursor),
+        ]);
+
+        if !Self::_can_view_relations(caller, principal, &relation_type) {
+            return Err(api_error(
+                ApiErrorType::Unauthorized,
+                "RELATIONS_HIDDEN",
+                "The relations of this profile are not visible to you",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "get_relations_paged",
+                inputs,
+            ));
+        }
+
+        let profiles = ENTRIES.with(Data::get_entries);
+
+        let mut relations: Vec<RelationWithProfileResponse> =
+            Self::_get_relations_by_type(principal, relation_type.clone())
+                .into_iter()
+                .filter_map(|(other, relation)| {
+                    profiles
+                        .iter()
+                        .find(|(_, _profile)| _profile.principal == other)
+                        .map(|(_identifier, _profile)| RelationWithProfileResponse {
+                            relation: RelationResponse {
+                                principal: other,
+                                kind: relation_type.clone(),
+                                since: relation.since,
+                                initiated_by: relation.initiated_by,
+                            },
+                            profile: Self::_map_profile_to_profile_summary(_identifier, _profile),
+                        })
+                })
+                .collect();
+
+        match sort {
+            RelationSort::Since(direction) => match direction {
+                SortDirection::Asc => {
+                    relations.sort_by_key(|r| (r.relation.since, r.relation.principal))
+                }
+                SortDirection::Desc => {
+                    relations.sort_by_key(|r| Reverse((r.relation.since, r.relation.principal)))
+                }
+            },
+            RelationSort::Principal(direction) => match direction {
+                SortDirection::Asc => relations.sort_by_key(|r| r.relation.principal),
+                SortDirection::Desc => relations.sort_by_key(|r| Reverse(r.relation.principal)),
+            },
+        }
+
+        // Continue after the relation of the cursor
+        let start = match cursor {
+            None => 0,
+            Some(_cursor) => match relations
+                .iter()
+                .position(|r| r.relation.principal == _cursor)
+            {
+                Some(index) => index + 1,
+                None => {
+                    return Err(api_error(
+                        ApiErrorType::BadRequest,
+                        "INVALID_CURSOR",
+                        "The cursor does not match a relation",
+                        STABLE_DATA
+                            .with(|data| Data::get_name(data.borrow().get()))
+                            .as_str(),
+                        "get_relations_paged",
+                        inputs,
+                    ))
+                }
+            },
+        };
+
+        let has_next_page = start + limit < relations.len();
+        let data: Vec<RelationWithProfileResponse> =
+            relations.into_iter().skip(start).take(limit).collect();
+
+        let next_cursor = match has_next_page {
+            true => data.last().map(|r| r.relation.principal),
+            false => None,
+        };
+
+        Ok(CursorPagedRelationsResponse { data, next_cursor })
+    }
+
+    // Method to get who can see the relations of the caller
+    pub fn get_relations_visibility(caller: Principal) -> RelationsVisibility {
+        RELATIONS_VISIBILITY
+            .with(|visibility| visibility.borrow().get(&caller.to_string()))
+            .unwrap_or_default()
+    }
+
+    // Method to set who can see the relations of the caller
+    pub fn set_relations_visibility(
+        caller: Principal,
+        visibility: RelationsVisibility,
+    ) -> Result<RelationsVisibility, ApiError> {
+        if Self::_get_profile_from_caller(caller).is_none() {
+            return Err(Self::_profile_not_found_error(
+                "set_relations_visibility",
+                Some(vec![format!("visibility - {:?}", &visibility)]),
+            ));
+        }
+
+        RELATIONS_VISIBILITY.with(|_visibility| {
+            _visibility
+                .borrow_mut()
+                .insert(caller.to_string(), visibility.clone())
+        });
+        Ok(visibility)
+    }
+
+    // Method to check if the caller can see the relations of a type of a principal
+    fn _can_view_relations(
+        caller: Principal,
+        principal: Principal,
+        relation_type: &RelationType,
+    ) -> bool {
+        if caller == principal {
+            return true;
+        }
+
+        // Blocks and mutes are never visible to others, nor are the relations of a profile that blocked the caller
+        if matches!(relation_type, RelationType::Blocked | RelationType::Muted)
+            || Self::_is_blocked_by(principal, caller)
+        {
+            return false;
+        }
+
+        match Self::get_relations_visibility(principal) {
+            RelationsVisibility::Public => true,
+            RelationsVisibility::Friends => Self::_get_relation(principal, caller)
+                .is_some_and(|relation| relation.kind == RelationType::Friend),
+            RelationsVisibility::OnlyMe => false,
+        }
+    }
+
+    // Method to get the relations of a profile by type, paged and sorted
+    pub fn get_paged_relations(
+        caller: Principal,
+        relation_type: RelationType,
+        limit: usize,
+        page: usize,
+        sort: RelationSort,
+    ) -> PagedResponse<RelationResponse> {
+        let mut relations: Vec<RelationResponse> =
+            Self::_get_relations_by_type(caller, relation_type.clone())
+                .into_iter()
+                .map(|(other, relation)| RelationResponse {
+                    principal: other,
+                    kind: relation_type.clone(),
+                    since: relation.since,
+                    initiated_by: relation.initiated_by,
+                })
+                .collect();
+
+        match sort {
+            RelationSort::Since(direction) => match direction {
+                SortDirection::Asc => relations.sort_by_key(|r| r.since),
+                SortDirection::Desc => relations.sort_by_key(|r| Reverse(r.since)),
+            },
+            RelationSort::Principal(direction) => match direction {
+                SortDirection::Asc => relations.sort_by_key(|r| r.principal),
+                SortDirection::Desc => relations.sort_by_key(|r| Reverse(r.principal)),
+            },
+        }
+
+        get_paged_data(relations, limit, page)
+    }
+
+    // Method to get the profile of a principal, a profile is hidden for the principals it blocked
     pub fn get_profile_by_user_principal(
+        caller: Principal,
         principal: Principal,
     ) -> Result<ProfileResponse, ApiError> {
         // get the profile from the data store
         match Self::_get_profile_from_caller(principal) {
-            // If the profile does not exist, return an error
+            // A deleted profile should not resolve to anything else than the deletion
+            None if Self::_is_deleted(principal) => Err(Self::_profile_deleted_error(
+                "get_profile_by_user_principal",
+                Some(vec![format!("principal - {:?}", &principal)]),
+            )),
+            // If the profile does not exist or the caller is blocked, return an error
             None => Err(Self::_profile_not_found_error(
                 "get_profile_by_user_principal",
                 None,
             )),
+            Some((_, profile)) if Self::_is_blocked_by(profile.principal, caller) => Err(
+                Self::_profile_not_found_error("get_profile_by_user_principal", None),
+            ),
             // If the profile exists, continue
-            Some((_identifier, profile)) => Ok(Self::_map_profile_to_profile_response(
+            Some((_identifier, profile)) => Ok(Self::_map_profile_to_visible_profile_response(
+                caller,
                 _identifier.to_string(),
                 profile,
             )),
         }
     }
 
-    // Method to get the profile by an identifier
-    pub fn get_profile_by_identifier(identifier: Principal) -> Result<ProfileResponse, ApiError> {
+    // Method to get the profile by an identifier, a profile is hidden for the principals it blocked
+    pub fn get_profile_by_identifier(
+        caller: Principal,
+        identifier: Principal,
+    ) -> Result<ProfileResponse, ApiError> {
+        // A deleted profile should not resolve to anything else than the deletion
+        if Self::_is_deleted(identifier) {
+            return Err(Self::_profile_deleted_error(
+                "get_profile_by_identifier",
+                Some(vec![format!("identifier - {:?}", &identifier)]),
+            ));
+        }
+
         // get the profile from the data store
-        match STABLE_DATA
-            .with(|data| ENTRIES.with(|entries| Data::get_entry(data, entries, identifier)))
-        {
+        match Self::_get_entry(identifier, "get_profile_by_identifier") {
             // If the profile does not exist, return an error
             Err(err) => Err(err),
+            Ok((_, profile)) if Self::_is_blocked_by(profile.principal, caller) => {
+                Err(Self::_profile_not_found_error(
+                    "get_profile_by_identifier",
+                    Some(vec![format!("identifier - {:?}", &identifier)]),
+                ))
+            }
             // If the profile exists, continue
-            Ok((_identifier, profile)) => Ok(Self::_map_profile_to_profile_response(
+            Ok((_identifier, profile)) => Ok(Self::_map_profile_to_visible_profile_response(
+                caller,
                 _identifier.to_string(),
                 profile,
             )),
         }
     }
 
-    // Method to get profiles by a list of principals
-    pub fn get_profiles_by_user_principal(principals: Vec<Principal>) -> Vec<ProfileResponse> {
+    // Method to get profiles by a list of principals, profiles that blocked the caller are left out
+    pub fn get_profiles_by_user_principal(
+        caller: Principal,
+        principals: Vec<Principal>,
+    ) -> Vec<ProfileResponse> {
         // get the profiles from the data store
         let fetched_profiles = ENTRIES.with(|entries| Data::get_entries(entries));
 
@@ -696,27 +1526,45 @@ impl Store {
                 fetched_profiles
                     .iter()
                     // filter the profiles by the principal
-                    .find(|f| f.1.principal == principal)
+                    .find(|f| {
+                        f.1.principal == principal
+                            && !Self::_is_deleted(principal)
+                            && !Self::_is_blocked_by(principal, caller)
+                    })
                     .map(|(_identifier, profile)| {
-                        Self::_map_profile_to_profile_response(_identifier.clone(), profile.clone())
+                        Self::_map_profile_to_visible_profile_response(
+                            caller,
+                            _identifier.clone(),
+                            profile.clone(),
+                        )
                     })
             })
             .collect()
     }
 
-    // Method to get profiles by a list of identifiers
-    pub fn get_profiles_by_identifier(profile_identifiers: Vec<Principal>) -> Vec<ProfileResponse> {
+    // Method to get profiles by a list of identifiers, profiles that blocked the caller are left out
+    pub fn get_profiles_by_identifier(
+        caller: Principal,
+        profile_identifiers: Vec<Principal>,
+    ) -> Vec<ProfileResponse> {
         // create a vector to hold the profiles
         let mut profiles: Vec<ProfileResponse> = vec![];
 
         // filter the profiles by the principals passed in
         for identifier in profile_identifiers {
+            if Self::_is_deleted(identifier) {
+                continue;
+            }
             // get the profile from the data store
-            if let Ok((_identifier, profile)) = STABLE_DATA
-                .with(|data| ENTRIES.with(|entries| Data::get_entry(data, entries, identifier)))
+            if let Ok((_identifier, profile)) =
+                Self::_get_entry(identifier, "get_profiles_by_identifier")
             {
+                if Self::_is_blocked_by(profile.principal, caller) {
+                    continue;
+                }
                 // add the profile to the vector
-                profiles.push(Self::_map_profile_to_profile_response(
+                profiles.push(Self::_map_profile_to_visible_profile_response(
+                    caller,
                     _identifier.to_string(),
                     profile,
                 ));
@@ -739,10 +1587,8 @@ impl Store {
                     approved_date: time(),
                 };
 
-                let _ = STABLE_DATA.with(|data| {
-                    ENTRIES
-                        .with(|entries| Data::update_entry(data, entries, _identifier, _existing))
-                });
+                let _ =
+                    Self::_update_entry(caller, "approve_code_of_conduct", _identifier, _existing);
                 Ok(true)
             }
         }
@@ -760,10 +1606,8 @@ impl Store {
                     approved_date: time(),
                 });
 
-                let _ = STABLE_DATA.with(|data| {
-                    ENTRIES
-                        .with(|entries| Data::update_entry(data, entries, _identifier, _existing))
-                });
+                let _ =
+                    Self::_update_entry(caller, "approve_privacy_policy", _identifier, _existing);
                 Ok(true)
             }
         }
@@ -781,16 +1625,53 @@ impl Store {
                     approved_date: time(),
                 });
 
-                let _ = STABLE_DATA.with(|data| {
-                    ENTRIES
-                        .with(|entries| Data::update_entry(data, entries, _identifier, _existing))
-                });
+                let _ =
+                    Self::_update_entry(caller, "approve_terms_of_service", _identifier, _existing);
                 Ok(true)
             }
         }
     }
 
+    // Method to get which notifications the caller receives
+    pub fn get_notification_preferences(
+        caller: Principal,
+    ) -> Result<NotificationPreferences, ApiError> {
+        match Self::_get_profile_from_caller(caller) {
+            None => Err(Self::_profile_not_found_error(
+                "get_notification_preferences",
+                None,
+            )),
+            Some((_, profile)) => Ok(profile.notification_preferences),
+        }
+    }
+
+    // Method to set which notifications the caller receives
+    pub fn set_notification_preferences(
+        caller: Principal,
+        preferences: NotificationPreferences,
+    ) -> Result<NotificationPreferences, ApiError> {
+        match Self::_get_profile_from_caller(caller) {
+            None => Err(Self::_profile_not_found_error(
+                "set_notification_preferences",
+                None,
+            )),
+            Some((_identifier, mut _existing)) => {
+                _existing.notification_preferences = preferences;
+                Self::_update_entry(
+                    caller,
+                    "set_notification_preferences",
+                    _identifier,
+                    _existing,
+                )
+                .map(|(_, profile)| profile.notification_preferences)
+            }
+        }
+    }
+
+    // Method to get profiles by identifiers, paged, filtered and sorted on the fields the caller is allowed to see,
+    // profiles that blocked the caller are left out
     pub fn get_paged_profiles_by_identifier(
+        caller: Principal,
         identifiers: Vec<Principal>,
         limit: usize,
         page: usize,
@@ -806,8 +1687,14 @@ impl Store {
                 if let Ok((_identifier, _profile)) =
                     ENTRIES.with(|entries| Data::get_entry(&data, entries, identifier))
                 {
+                    if Self::_is_deleted(identifier)
+                        || Self::_is_blocked_by(_profile.principal, caller)
+                    {
+                        return;
+                    }
                     // add the profile to the vector
-                    profiles.push(Self::_map_profile_to_profile_response(
+                    profiles.push(Self::_map_profile_to_visible_profile_response(
+                        caller,
                         _identifier.to_string(),
                         _profile,
                     ))
@@ -822,7 +1709,10 @@ impl Store {
         })
     }
 
+    // Method to get profiles by principals, paged, filtered and sorted on the fields the caller is allowed to see,
+    // profiles that blocked the caller are left out
     pub fn get_paged_profiles_by_principal(
+        caller: Principal,
         principals: Vec<Principal>,
         limit: usize,
         page: usize,
@@ -836,11 +1726,12 @@ impl Store {
             let all_profiles = Data::get_entries(entries);
             // filter the profiles by the principals passed in
             principals.into_iter().for_each(|p| {
-                if let Some((_identifier, _profile)) =
-                    all_profiles.iter().find(|(_, _p)| _p.principal == p)
-                {
+                if let Some((_identifier, _profile)) = all_profiles.iter().find(|(_, _p)| {
+                    _p.principal == p && !Self::_is_deleted(p) && !Self::_is_blocked_by(p, caller)
+                }) {
                     // add the profile to the vector
-                    profiles.push(Self::_map_profile_to_profile_response(
+                    profiles.push(Self::_map_profile_to_visible_profile_response(
+                        caller,
                         _identifier.clone(),
                         _profile.clone(),
                     ));
@@ -865,6 +1756,35 @@ impl Store {
         }
     }
 
+    // Method to check if a profile was erased, by the identifier or the principal of the profile
+    fn _is_deleted(key: Principal) -> bool {
+        TOMBSTONES.with(|tombstones| tombstones.borrow().contains_key(&key.to_string()))
+    }
+
+    fn _profile_deleted_error(method_name: &str, inputs: Option<Vec<String>>) -> ApiError {
+        api_error(
+            ApiErrorType::NotFound,
+            "PROFILE_DELETED",
+            "Profile has been deleted",
+            STABLE_DATA
+                .with(|data| Data::get_name(data.borrow().get()))
+                .as_str(),
+            method_name,
+            inputs,
+        )
+    }
+
+    // Method to check if a username is still reserved for a different principal
+    fn _is_username_reserved(username: &String, caller: Principal) -> bool {
+        match USERNAME_HISTORY.with(|history| history.borrow().get(username)) {
+            None => false,
+            Some(_change) => {
+                _change.principal != caller
+                    && _change.changed_on + USERNAME_RESERVATION_PERIOD > time()
+            }
+        }
+    }
+
     // Method to check if a profile exists by email
     fn _has_email(profiles: &Vec<(String, Profile)>, email: &String) -> bool {
         let profile = profiles.iter().find(|(_, profile)| &profile.email == email);
@@ -926,7 +1846,8 @@ impl Store {
         profiles
     }
 
-    // Method to filter profiles by a filter
+    // Method to filter profiles by a filter, the profiles should only contain the fields the caller is allowed to see,
+    // so hidden values can not be guessed by filtering on them
     fn _get_filtered_profiles(
         mut profiles: Vec<ProfileResponse>,
         filters: Vec<ProfileFilter>,
@@ -1078,6 +1999,48 @@ impl Store {
         }
     }
 
+    // Method to map a profile to a response with the fields the caller is not allowed to see emptied
+    fn _map_profile_to_visible_profile_response(
+        caller: Principal,
+        identifier: String,
+        profile: Profile,
+    ) -> ProfileResponse {
+        let owner = profile.principal;
+        let mut response = Self::_map_profile_to_profile_response(identifier, profile);
+        if caller == owner {
+            return response;
+        }
+
+        for visibility in Self::get_field_visibility(owner) {
+            if Self::_is_in_audience(owner, caller, &visibility.audience) {
+                continue;
+            }
+            match visibility.field {
+                ProfileField::FirstName => response.first_name = String::default(),
+                ProfileField::LastName => response.last_name = String::default(),
+                ProfileField::Email => response.email = String::default(),
+                ProfileField::DateOfBirth => response.date_of_birth = 0,
+                ProfileField::About => response.about = String::default(),
+                ProfileField::City => response.city = String::default(),
+                ProfileField::StateOrProvince => response.state_or_province = String::default(),
+                ProfileField::Country => response.country = String::default(),
+                ProfileField::Website => response.website = String::default(),
+            }
+        }
+        response
+    }
+
+    // Method to map a profile to the summary that is shown with friend requests and suggestions
+    fn _map_profile_to_profile_summary(identifier: &str, profile: &Profile) -> ProfileSummary {
+        ProfileSummary {
+            principal: profile.principal,
+            identifier: Principal::from_text(identifier).unwrap_or(Principal::anonymous()),
+            username: profile.username.clone(),
+            display_name: profile.display_name.clone(),
+            profile_image: profile.profile_image.clone(),
+        }
+    }
+
     //  Method to get a profile from a caller
     fn _get_profile_from_caller(caller: Principal) -> Option<(Principal, Profile)> {
         let profiles = ENTRIES.with(|entries| Data::get_entries(entries));
@@ -1094,6 +2057,26 @@ impl Store {
         }
     }
 
+    // Same as `Data::get_entry`, but the error is created with the system API of this canister
+    fn _get_entry(
+        identifier: Principal,
+        method_name: &str,
+    ) -> Result<(Principal, Profile), ApiError> {
+        match ENTRIES.with(|entries| entries.borrow().get(&identifier.to_string())) {
+            None => Err(api_error(
+                ApiErrorType::BadRequest,
+                "ENTRY_NOT_FOUND",
+                "The entry tied to this ID cant be found",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                method_name,
+                Some(vec![format!("entry_id - {}", &identifier)]),
+            )),
+            Some(profile) => Ok((identifier, profile)),
+        }
+    }
+
     // default profile_not_found error
     fn _profile_not_found_error(method_name: &str, inputs: Option<Vec<String>>) -> ApiError {
         api_error(
@@ -1110,7 +2093,8 @@ impl Store {
 
     // Used for composite_query calls from the parent canister
     //
-    // Method to get filtered profiles serialized and chunked
+    // Method to get filtered profiles serialized and chunked, the results are public,
+    // so only the fields an anonymous viewer is allowed to see are returned and filtered on
     pub fn get_chunked_data(
         filters: Vec<ProfileFilter>,
         chunk: usize,
@@ -1120,8 +2104,15 @@ impl Store {
         // get profiles for filtering
         let mapped_profiles: Vec<ProfileResponse> = profiles
             .iter()
+            .filter(|(_identifier, _profile_data)| {
+                !TOMBSTONES.with(|tombstones| tombstones.borrow().contains_key(_identifier))
+            })
             .map(|(_identifier, _profile_data)| {
-                Self::_map_profile_to_profile_response(_identifier.clone(), _profile_data.clone())
+                Self::_map_profile_to_visible_profile_response(
+                    Principal::anonymous(),
+                    _identifier.clone(),
+                    _profile_data.clone(),
+                )
             })
             .collect();
 
@@ -1163,14 +2154,52 @@ impl Store {
         to: Principal,
         message: String,
     ) -> Result<FriendRequestResponse, ApiError> {
-        FRIEND_REQUEST.with(|r| {
-            let mut requests = r.borrow_mut();
+        let inputs = Some(vec![
+            format!("requested_by - {:?}", &requested_by.to_string()),
+            format!("to - {:?}", &to.to_string()),
+        ]);
 
-            // If the requester puts out a second friend request for the user
-            if requests
-                .iter()
-                .any(|(_, r)| r.requested_by == requested_by && r.to == to)
-            {
+        // Both the requester and the invited user need a profile
+        if Self::_get_profile_from_caller(requested_by).is_none() {
+            return Err(Self::_profile_not_found_error("friend_request", inputs));
+        }
+
+        if requested_by == to || Self::_get_profile_from_caller(to).is_none() {
+            return Err(api_error(
+                ApiErrorType::NotFound,
+                "INVALID_RECEIVER",
+                "The invited user does not have a profile",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "friend_request",
+                inputs,
+            ));
+        }
+
+        Self::_check_friend_request_limits(requested_by, inputs)?;
+
+        // Friend requests are not possible when one of both blocked the other
+        if Self::_is_blocked_by(to, requested_by) || Self::_is_blocked_by(requested_by, to) {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "BLOCKED",
+                "You can not send a friend request to this user",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "friend_request",
+                None,
+            ));
+        }
+
+        FRIEND_REQUEST.with(|r| {
+            let mut requests = r.borrow_mut();
+
+            // If the requester puts out a second friend request for the user
+            if requests.iter().any(|(_, r)| {
+                r.requested_by == requested_by && r.to == to && Self::_is_friend_request_open(&r)
+            }) {
                 return Err(api_error(
                     ApiErrorType::BadRequest,
                     "ALREADY_REQUESTED",
@@ -1184,10 +2213,9 @@ impl Store {
             }
 
             // if the "to" has already sent a request to the "requested_by"
-            if requests
-                .iter()
-                .any(|(_, r)| r.requested_by == to && r.to == requested_by)
-            {
+            if requests.iter().any(|(_, r)| {
+                r.requested_by == to && r.to == requested_by && Self::_is_friend_request_open(&r)
+            }) {
                 return Err(api_error(
                     ApiErrorType::BadRequest,
                     "PENDING_REQUEST",
@@ -1210,9 +2238,10 @@ impl Store {
             };
 
             requests.insert(id.clone(), request.clone());
+            Self::_add_friend_request_sent(requested_by, request.created_at);
 
-            let display_name = Self::get_profile_by_user_principal(requested_by)
-                .map_or("unknown".to_string(), |p| p.display_name);
+            let display_name = Self::_get_profile_from_caller(requested_by)
+                .map_or("unknown".to_string(), |(_, p)| p.display_name);
 
             let metadata = json!({
                 "receivedBy": display_name,
@@ -1221,17 +2250,24 @@ impl Store {
                 "isProcessed": false,
             });
 
-            Self::send_notification().friend_request_notification(
-                requested_by.clone(),
-                FriendRequestNotificationData {
-                    friend_request_id: id.clone(),
-                    from: requested_by.clone(),
-                    to,
-                    accepted: None,
-                },
-                vec![to.clone()],
-                metadata.to_string(),
-            );
+            // Notifications are not send to principals that muted the sender or turned them off
+            if !Self::_is_muted_by(to, requested_by)
+                && Self::_is_notification_enabled(to, |preferences| &preferences.friend_request)
+            {
+                Self::_notify(
+                    requested_by,
+                    SendNotificationData {
+                        data: NotificationType::FriendRequest(FriendRequestNotificationData {
+                            friend_request_id: id,
+                            from: requested_by,
+                            to,
+                            accepted: None,
+                        }),
+                        receivers: vec![to],
+                    },
+                    metadata.to_string(),
+                );
+            }
 
             Ok(FriendRequestResponse {
                 id,
@@ -1239,10 +2275,12 @@ impl Store {
                 message: request.message.clone(),
                 to,
                 created_at: request.created_at,
+                expires_at: Self::_get_friend_request_expiry(&request),
             })
         })
     }
 
+    // Method to get the open friend requests send by or to the caller, expired requests that are not cleaned up yet are left out
     pub fn get_friend_requests(caller: Principal) -> Vec<FriendRequestResponse> {
         FRIEND_REQUEST.with(|r| {
             let requests = r.borrow();
@@ -1250,252 +2288,899 @@ impl Store {
             requests
                 .iter()
                 .filter(|(_, r)| r.requested_by == caller || r.to == caller)
-                .map(|(k, v)| FriendRequestResponse {
-                    id: k.clone(),
-                    requested_by: v.requested_by,
-                    message: v.message.clone(),
-                    to: v.to,
-                    created_at: v.created_at,
-                })
+                .filter(|(_, r)| Self::_is_friend_request_open(r))
+                .map(|(k, v)| Self::_map_friend_request_to_response(k, &v))
                 .collect()
         })
     }
 
-    pub fn accept_friend_request(caller: Principal, id: u64) -> Result<bool, String> {
-        FRIEND_REQUEST.with(|r| {
-            let mut requests = r.borrow_mut();
+    // Method to get the friend requests send to the caller, paged and sorted on creation date
+    pub fn get_incoming_friend_requests(
+        caller: Principal,
+        limit: usize,
+        page: usize,
+        sort: SortDirection,
+        include_profile: bool,
+    ) -> PagedResponse<FriendRequestWithProfileResponse> {
+        Self::_get_paged_friend_requests(
+            caller,
+            |request| request.to == caller,
+            |request| request.requested_by,
+            limit,
+            page,
+            sort,
+            include_profile,
+        )
+    }
 
-            if let Some(request) = requests.get(&id) {
-                if request.to != caller {
-                    return Err("Request not found".to_string());
-                }
-                let profiles = ENTRIES.with(|data| Data::get_entries(data));
+    // Method to get the friend requests send by the caller, paged and sorted on creation date
+    pub fn get_outgoing_friend_requests(
+        caller: Principal,
+        limit: usize,
+        page: usize,
+        sort: SortDirection,
+        include_profile: bool,
+    ) -> PagedResponse<FriendRequestWithProfileResponse> {
+        Self::_get_paged_friend_requests(
+            caller,
+            |request| request.requested_by == caller,
+            |request| request.to,
+            limit,
+            page,
+            sort,
+            include_profile,
+        )
+    }
 
-                let mut caller_profile = profiles
-                    .iter()
-                    .find(|(_, p)| p.principal == request.to)
-                    .unwrap()
-                    .clone();
+    fn _get_paged_friend_requests(
+        caller: Principal,
+        filter: impl Fn(&FriendRequest) -> bool,
+        other_party: impl Fn(&FriendRequest) -> Principal,
+        limit: usize,
+        page: usize,
+        sort: SortDirection,
+        include_profile: bool,
+    ) -> PagedResponse<FriendRequestWithProfileResponse> {
+        let mut requests: Vec<(u64, FriendRequest)> = FRIEND_REQUEST.with(|requests| {
+            requests
+                .borrow()
+                .iter()
+                .filter(|(_, request)| filter(request) && Self::_is_friend_request_open(request))
+                .collect()
+        });
+
+        match sort {
+            SortDirection::Asc => requests.sort_by_key(|(id, r)| (r.created_at, *id)),
+            SortDirection::Desc => requests.sort_by_key(|(id, r)| Reverse((r.created_at, *id))),
+        }
 
-                caller_profile
-                    .1
-                    .relations
-                    .insert(request.requested_by, RelationType::Friend.to_string());
+        let paged = get_paged_data(requests, limit, page);
 
-                let mut to_profile = profiles
+        // The profiles are only loaded when a summary is requested
+        let profiles = match include_profile {
+            true => ENTRIES.with(Data::get_entries),
+            false => vec![],
+        };
+
+        let data = paged
+            .data
+            .into_iter()
+            .map(|(id, request)| {
+                let other = other_party(&request);
+                let profile = profiles
                     .iter()
-                    .find(|(_, p)| p.principal == request.requested_by)
-                    .unwrap()
-                    .clone();
-
-                to_profile
-                    .1
-                    .relations
-                    .insert(request.to, RelationType::Friend.to_string());
-
-                ENTRIES.with(|entries| {
-                    let _ = STABLE_DATA.with(|data| {
-                        let _ = Data::update_entry(
-                            data,
-                            entries,
-                            Principal::from_text(caller_profile.0).unwrap(),
-                            caller_profile.1,
-                        );
-                        let _ = Data::update_entry(
-                            data,
-                            entries,
-                            Principal::from_text(to_profile.0.clone()).unwrap(),
-                            to_profile.1.clone(),
-                        );
+                    .find(|(_, _profile)| _profile.principal == other)
+                    .filter(|_| !Self::_is_blocked_by(other, caller))
+                    .map(|(_identifier, _profile)| {
+                        Self::_map_profile_to_profile_summary(_identifier, _profile)
                     });
-                });
-                requests.remove(&id);
+                FriendRequestWithProfileResponse {
+                    request: Self::_map_friend_request_to_response(id, &request),
+                    profile,
+                }
+            })
+            .collect();
 
-                let display_name = Self::get_profile_by_user_principal(caller)
-                    .map_or("unknown".to_string(), |p| p.display_name);
+        PagedResponse {
+            page: paged.page,
+            limit: paged.limit,
+            total: paged.total,
+            number_of_pages: paged.number_of_pages,
+            data,
+        }
+    }
 
-                let metadata = json!({
-                    "acceptedBy": display_name,
-                    "acceptedByPrincipal": caller.to_string(),
-                });
+    fn _map_friend_request_to_response(id: u64, request: &FriendRequest) -> FriendRequestResponse {
+        FriendRequestResponse {
+            id,
+            requested_by: request.requested_by,
+            message: request.message.clone(),
+            to: request.to,
+            created_at: request.created_at,
+            expires_at: Self::_get_friend_request_expiry(request),
+        }
+    }
+
+    // Method to get the time a friend request stays open before it expires
+    pub fn get_friend_request_ttl() -> u64 {
+        FRIEND_REQUEST_TTL.with(|ttl| *ttl.borrow().get())
+    }
+
+    // Method to change the time a friend request stays open, also applies to the open requests
+    pub fn set_friend_request_ttl(ttl: u64) -> Result<u64, ApiError> {
+        if ttl == 0 {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "INVALID_TTL",
+                "The time to live should be greater than zero",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "set_friend_request_ttl",
+                Some(vec![format!("ttl - {:?}", &ttl)]),
+            ));
+        }
+
+        let _ = FRIEND_REQUEST_TTL.with(|_ttl| _ttl.borrow_mut().set(ttl));
+        Ok(ttl)
+    }
+
+    // Method to remove the friend requests that passed their expiry and notify the requesters, triggered by a timer
+    pub fn expire_friend_requests() {
+        let now = time();
+        let expired: Vec<(u64, FriendRequest)> = FRIEND_REQUEST.with(|requests| {
+            requests
+                .borrow()
+                .iter()
+                .filter(|(_, request)| Self::_get_friend_request_expiry(request) <= now)
+                .collect()
+        });
+
+        FRIEND_REQUEST.with(|requests| {
+            let mut requests = requests.borrow_mut();
+            for (id, _) in expired.iter() {
+                requests.remove(id);
+            }
+        });
+
+        for (id, request) in expired {
+            let metadata = json!({
+                "friendRequestId": id,
+                "to": request.to.to_string(),
+                "expiredAt": now,
+            });
 
-                Self::send_notification().friend_request_notification(
-                    request.requested_by.clone(),
-                    FriendRequestNotificationData {
-                        friend_request_id: id.clone(),
-                        from: request.requested_by.clone(),
-                        to: request.to.clone(),
-                        accepted: Some(true),
+            if Self::_is_notification_enabled(request.requested_by, |preferences| {
+                &preferences.friend_request_expired
+            }) {
+                Self::_notify(
+                    request.requested_by,
+                    SendNotificationData {
+                        data: NotificationType::Misc("friend_request_expired".to_string()),
+                        receivers: vec![request.requested_by],
                     },
-                    vec![request.requested_by],
                     metadata.to_string(),
                 );
-                return Ok(true);
             }
+        }
+    }
+
+    // Method to get the rate limits for sending friend requests
+    pub fn get_friend_request_limits() -> FriendRequestLimits {
+        FRIEND_REQUEST_LIMITS.with(|limits| limits.borrow().get().clone())
+    }
 
-            Err("Request not found".to_string())
+    // Method to change the rate limits for sending friend requests
+    pub fn set_friend_request_limits(
+        limits: FriendRequestLimits,
+    ) -> Result<FriendRequestLimits, ApiError> {
+        if limits.per_hour == 0 || limits.per_day == 0 || limits.max_pending == 0 {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "INVALID_LIMITS",
+                "The limits should be greater than zero",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "set_friend_request_limits",
+                Some(vec![format!("limits - {:?}", &limits)]),
+            ));
+        }
+
+        let _ = FRIEND_REQUEST_LIMITS.with(|_limits| _limits.borrow_mut().set(limits.clone()));
+        Ok(limits)
+    }
+
+    // Method to get the times the requester sent friend requests in the last day
+    fn _get_friend_requests_sent(requested_by: Principal, now: u64) -> Vec<u64> {
+        FRIEND_REQUESTS_SENT.with(|sent| {
+            sent.borrow()
+                .get(&requested_by.to_string())
+                .map_or(vec![], |sent| sent.sent_on)
+                .into_iter()
+                .filter(|sent_on| now.saturating_sub(*sent_on) < DAY_IN_NANOS)
+                .collect()
         })
     }
 
-    pub fn remove_friend(caller: Principal, to_remove: Principal) -> Result<bool, String> {
-        let profiles = ENTRIES.with(|data| Data::get_entries(data));
+    // Method to store the time a friend request was sent, send times older than a day are dropped
+    fn _add_friend_request_sent(requested_by: Principal, sent_on: u64) {
+        let mut sent = Self::_get_friend_requests_sent(requested_by, sent_on);
+        sent.push(sent_on);
+        FRIEND_REQUESTS_SENT.with(|_sent| {
+            _sent.borrow_mut().insert(
+                requested_by.to_string(),
+                SentFriendRequests { sent_on: sent },
+            )
+        });
+    }
 
-        let mut caller_profile = profiles
-            .iter()
-            .find(|(_, p)| p.principal == caller)
-            .unwrap()
-            .clone();
+    // Method to forget the send times that are older than a day, requesters without recent requests are removed
+    pub fn prune_friend_requests_sent() {
+        let now = time();
+        FRIEND_REQUESTS_SENT.with(|sent| {
+            let mut sent = sent.borrow_mut();
+            let entries: Vec<(String, SentFriendRequests)> = sent.iter().collect();
+            for (requested_by, mut requests) in entries {
+                requests
+                    .sent_on
+                    .retain(|sent_on| now.saturating_sub(*sent_on) < DAY_IN_NANOS);
+                if requests.sent_on.is_empty() {
+                    sent.remove(&requested_by);
+                } else {
+                    sent.insert(requested_by, requests);
+                }
+            }
+        });
+    }
 
-        caller_profile.1.relations.remove(&to_remove);
+    // Method to check if the requester is within the rate limits and the pending request cap
+    fn _check_friend_request_limits(
+        requested_by: Principal,
+        inputs: Option<Vec<String>>,
+    ) -> Result<(), ApiError> {
+        let limits = Self::get_friend_request_limits();
+        let now = time();
 
-        let mut to_remove_profile = profiles
-            .iter()
-            .find(|(_, p)| p.principal == to_remove)
-            .unwrap()
-            .clone();
+        let sent = Self::_get_friend_requests_sent(requested_by, now);
 
-        to_remove_profile.1.relations.remove(&caller);
+        let sent_last_hour = sent
+            .iter()
+            .filter(|created_at| now.saturating_sub(**created_at) < HOUR_IN_NANOS)
+            .count() as u64;
 
-        ENTRIES.with(|entries| {
-            STABLE_DATA.with(|data| {
-                let _ = Data::update_entry(
-                    data,
-                    entries,
-                    Principal::from_text(caller_profile.0).unwrap(),
-                    caller_profile.1,
-                );
-                let _ = Data::update_entry(
-                    data,
-                    entries,
-                    Principal::from_text(to_remove_profile.0).unwrap(),
-                    to_remove_profile.1,
-                );
-            });
+        let pending = FRIEND_REQUEST.with(|requests| {
+            requests
+                .borrow()
+                .iter()
+                .filter(|(_, request)| {
+                    request.requested_by == requested_by && Self::_is_friend_request_open(request)
+                })
+                .count() as u64
         });
 
-        Self::send_notification().friend_remove_notification(to_remove, "{}".to_string());
+        let error = |tag: &str, message: &str| {
+            Err(api_error(
+                ApiErrorType::BadRequest,
+                tag,
+                message,
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "friend_request",
+                inputs.clone(),
+            ))
+        };
 
-        Ok(true)
-    }
+        if sent_last_hour >= limits.per_hour {
+            return error(
+                "HOURLY_LIMIT_REACHED",
+                "You sent too many friend requests in the last hour",
+            );
+        }
 
-    pub fn clear_relations(caller: Principal) -> bool {
-        let profiles = ENTRIES.with(|data| Data::get_entries(data));
+        if sent.len() as u64 >= limits.per_day {
+            return error(
+                "DAILY_LIMIT_REACHED",
+                "You sent too many friend requests in the last day",
+            );
+        }
 
-        let mut caller_profile = profiles
-            .iter()
-            .find(|(_, p)| p.principal == caller)
-            .unwrap()
-            .clone();
+        if pending >= limits.max_pending {
+            return error(
+                "TOO_MANY_PENDING_REQUESTS",
+                "You have too many friend requests waiting for a response",
+            );
+        }
 
-        caller_profile.1.relations.clear();
+        Ok(())
+    }
 
-        ENTRIES.with(|entries| {
-            STABLE_DATA.with(|data| {
-                let _ = Data::update_entry(
-                    data,
-                    entries,
-                    Principal::from_text(caller_profile.0).unwrap(),
-                    caller_profile.1,
-                );
-            });
-        });
+    fn _get_friend_request_expiry(request: &FriendRequest) -> u64 {
+        request
+            .created_at
+            .saturating_add(Self::get_friend_request_ttl())
+    }
 
-        true
+    // Method to check if a friend request is not expired, expired requests are only removed by a timer
+    fn _is_friend_request_open(request: &FriendRequest) -> bool {
+        Self::_get_friend_request_expiry(request) > time()
     }
 
-    pub fn decline_friend_request(caller: Principal, id: u64) -> Result<bool, String> {
+    pub fn accept_friend_request(caller: Principal, id: u64) -> Result<bool, ApiError> {
         FRIEND_REQUEST.with(|r| {
             let mut requests = r.borrow_mut();
 
             if let Some(request) = requests.get(&id) {
-                if request.to == caller {
-                    let display_name = Self::get_profile_by_user_principal(caller)
-                        .map_or("unknown".to_string(), |p| p.display_name);
+                if request.to != caller {
+                    return Err(Self::_friend_request_not_found_error(
+                        "accept_friend_request",
+                        id,
+                    ));
+                }
+                // A request that expired but is not cleaned up yet can not be accepted anymore
+                if Self::_get_friend_request_expiry(&request) <= time() {
+                    return Err(api_error(
+                        ApiErrorType::BadRequest,
+                        "REQUEST_EXPIRED",
+                        "Friend request expired",
+                        STABLE_DATA
+                            .with(|data| Data::get_name(data.borrow().get()))
+                            .as_str(),
+                        "accept_friend_request",
+                        Some(vec![format!("id - {:?}", &id)]),
+                    ));
+                }
+                // The friendship is stored on both sides, initiated by the requester
+                Self::_set_relation(
+                    request.to,
+                    request.requested_by,
+                    RelationType::Friend,
+                    request.requested_by,
+                    caller,
+                    "accept_friend_request",
+                );
+                Self::_set_relation(
+                    request.requested_by,
+                    request.to,
+                    RelationType::Friend,
+                    request.requested_by,
+                    caller,
+                    "accept_friend_request",
+                );
+                requests.remove(&id);
 
-                    let metadata = json!({
-                        "declinedBy": display_name,
-                        "declinedByPrincipal": caller.to_string(),
-                    });
+                let display_name = Self::_get_profile_from_caller(caller)
+                    .map_or("unknown".to_string(), |(_, p)| p.display_name);
+
+                let metadata = json!({
+                    "acceptedBy": display_name,
+                    "acceptedByPrincipal": caller.to_string(),
+                });
 
-                    Self::send_notification().friend_request_notification(
-                        request.requested_by.clone(),
-                        FriendRequestNotificationData {
-                            friend_request_id: id.clone(),
-                            from: request.requested_by.clone(),
-                            to: request.to.clone(),
-                            accepted: Some(false),
+                if !Self::_is_muted_by(request.requested_by, caller)
+                    && Self::_is_notification_enabled(request.requested_by, |preferences| {
+                        &preferences.friend_request_accepted
+                    })
+                {
+                    Self::_notify(
+                        request.requested_by,
+                        SendNotificationData {
+                            data: NotificationType::FriendRequest(FriendRequestNotificationData {
+                                friend_request_id: id,
+                                from: request.requested_by,
+                                to: request.to,
+                                accepted: Some(true),
+                            }),
+                            receivers: vec![request.requested_by],
                         },
-                        vec![request.requested_by],
                         metadata.to_string(),
                     );
-
-                    requests.remove(&id);
-                    return Ok(true);
                 }
+                return Ok(true);
             }
 
-            Err("Request not found".to_string())
+            Err(Self::_friend_request_not_found_error(
+                "accept_friend_request",
+                id,
+            ))
         })
     }
 
-    pub fn remove_friend_request(caller: Principal, id: u64) -> Result<bool, String> {
-        FRIEND_REQUEST.with(|r| {
-            let mut requests = r.borrow_mut();
+    pub fn remove_friend(caller: Principal, to_remove: Principal) -> Result<bool, ApiError> {
+        let is_friend = |owner: Principal, other: Principal| {
+            Self::_get_relation(owner, other)
+                .is_some_and(|relation| relation.kind == RelationType::Friend)
+        };
 
-            if let Some(request) = requests.get(&id) {
-                if request.requested_by == caller {
-                    requests.remove(&id);
-                    return Ok(true);
-                }
-            }
+        // Also removes a friendship that is only stored on one of the sides
+        if !is_friend(caller, to_remove) && !is_friend(to_remove, caller) {
+            return Err(api_error(
+                ApiErrorType::NotFound,
+                "FRIEND_NOT_FOUND",
+                "Friend not found",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "remove_friend",
+                Some(vec![format!("principal - {:?}", &to_remove.to_string())]),
+            ));
+        }
 
-            Err("Request not found".to_string())
-        })
-    }
+        Self::_remove_relation(caller, to_remove, caller, "remove_friend");
+        Self::_remove_relation(to_remove, caller, caller, "remove_friend");
 
-    pub fn block_user(caller: Principal, to_block: Principal) -> Result<ProfileResponse, ApiError> {
-        let inputs = Some(vec![
-            format!("principal - {:?}", &caller.to_string()),
-            format!("relation_identifier - {:?}", &to_block.to_string()),
-        ]);
+        if !Self::_is_muted_by(to_remove, caller)
+            && Self::_is_notification_enabled(to_remove, |preferences| &preferences.friend_removed)
+        {
+            Self::_notify_silent(
+                SendNotificationData {
+                    data: NotificationType::FriendRemove(to_remove),
+                    receivers: vec![to_remove],
+                },
+                "{}".to_string(),
+            );
+        }
 
-        // get the profile from the data store
-        match Self::_get_profile_from_caller(caller) {
-            // If the profile does not exist, return an error
-            None => Err(Self::_profile_not_found_error("block_user", inputs)),
-            // If the profile exists, continue
-            Some((_identifier, mut _profile)) => {
-                // Add the relation to the profile, if existing it will be overwritten
-                _profile
-                    .relations
-                    .insert(to_block, RelationType::Blocked.to_string());
+        Ok(true)
+    }
 
-                // Update the profile in the data store
-                ENTRIES
-                    .with(|entries| {
-                        STABLE_DATA
-                            .with(|data| Data::update_entry(data, entries, _identifier, _profile))
-                    })
-                    .map_or_else(
-                        |err| Err(err),
-                        |result| {
-                            Ok(Self::_map_profile_to_profile_response(
-                                result.0.to_string(),
-                                result.1,
-                            ))
-                        },
-                    )
-            }
+    // Method to request a nonce that confirms clearing all relations of the caller
+    pub async fn request_clear_relations(
+        caller: Principal,
+    ) -> Result<ClearRelationsRequest, ApiError> {
+        if Self::_get_profile_from_caller(caller).is_none() {
+            return Err(Self::_profile_not_found_error(
+                "request_clear_relations",
+                None,
+            ));
         }
+
+        let nonce = match raw_rand().await {
+            Ok((bytes,)) => bytes
+                .iter()
+                .take(16)
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>(),
+            Err(err) => {
+                return Err(api_error(
+                    ApiErrorType::Unexpected,
+                    "NONCE_NOT_CREATED",
+                    err.1.as_str(),
+                    STABLE_DATA
+                        .with(|data| Data::get_name(data.borrow().get()))
+                        .as_str(),
+                    "request_clear_relations",
+                    None,
+                ))
+            }
+        };
+
+        let expires_at = time() + CLEAR_RELATIONS_NONCE_TTL;
+        CLEAR_RELATIONS_NONCES.with(|nonces| {
+            nonces
+                .borrow_mut()
+                .insert(caller, (nonce.clone(), expires_at))
+        });
+
+        Ok(ClearRelationsRequest { nonce, expires_at })
     }
 
-    pub fn unblock_user(
+    // Method to clear all friends, blocks and follows of the caller on both sides of the relation,
+    // including the relations stored on the other child canisters
+    pub async fn confirm_clear_relations(
         caller: Principal,
-        to_unblock: Principal,
-    ) -> Result<ProfileResponse, ApiError> {
-        let inputs = Some(vec![
-            format!("principal - {:?}", &caller.to_string()),
-            format!("relation_identifier - {:?}", &to_unblock.to_string()),
+        nonce: String,
+    ) -> Result<ClearRelationsSummary, ApiError> {
+        let inputs = Some(vec![format!("nonce - {:?}", &nonce)]);
+
+        if Self::_get_profile_from_caller(caller).is_none() {
+            return Err(Self::_profile_not_found_error(
+                "confirm_clear_relations",
+                inputs,
+            ));
+        }
+
+        // A nonce can only be used once, also when it is invalid
+        let error = match CLEAR_RELATIONS_NONCES.with(|nonces| nonces.borrow_mut().remove(&caller))
+        {
+            None => Some((
+                "NONCE_NOT_FOUND",
+                "Request a nonce before clearing the relations",
+            )),
+            Some((_nonce, _)) if _nonce != nonce => Some(("INVALID_NONCE", "The nonce is invalid")),
+            Some((_, expires_at)) if expires_at < time() => {
+                Some(("NONCE_EXPIRED", "The nonce has expired"))
+            }
+            Some(_) => None,
+        };
+
+        if let Some((tag, message)) = error {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                tag,
+                message,
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "confirm_clear_relations",
+                inputs,
+            ));
+        }
+
+        Ok(Self::_clear_relations_everywhere(caller).await)
+    }
+
+    // Method to clear all relations of the caller with the confirmation code, replaced by the nonce flow
+    pub async fn clear_relations(caller: Principal, code: String) -> bool {
+        if code != CLEAR_RELATIONS_CODE || Self::_get_profile_from_caller(caller).is_none() {
+            return false;
+        }
+
+        Self::_clear_relations_everywhere(caller).await;
+        true
+    }
+
+    // Method to clear the relations of a principal on this canister and on the other child canisters,
+    // when the other child canisters can not be looked up the summary only contains the local relations
+    async fn _clear_relations_everywhere(principal: Principal) -> ClearRelationsSummary {
+        let mut summary = Self::_clear_relations_locally(principal);
+
+        let siblings = match Self::_get_sibling_canisters().await {
+            Ok(siblings) => siblings,
+            Err(_) => {
+                summary.sibling_lookup_failed = true;
+                return summary;
+            }
+        };
+
+        for canister in siblings {
+            let result: Result<(Result<ClearRelationsSummary, ApiError>,), _> =
+                call::call(canister, "clear_relations_by_sibling", (principal,)).await;
+
+            match result {
+                Ok((Ok(_summary),)) => summary.merge(_summary),
+                _ => summary.failed_canisters.push(canister),
+            }
+        }
+
+        summary
+    }
+
+    // Method to clear the relations of a principal on this canister, can only be called by another child canister
+    pub async fn clear_relations_by_sibling(
+        sibling: Principal,
+        principal: Principal,
+    ) -> Result<ClearRelationsSummary, ApiError> {
+        let unknown_canister = || {
+            Err(api_error(
+                ApiErrorType::Unauthorized,
+                "UNKNOWN_CANISTER",
+                "The caller is not a sibling of this canister",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "clear_relations_by_sibling",
+                Some(vec![format!("principal - {:?}", &principal)]),
+            ))
+        };
+
+        // Only canisters can be siblings, other callers are rejected without asking the parent
+        if sibling.as_slice().last() != Some(&OPAQUE_PRINCIPAL_CLASS) || sibling == id() {
+            return unknown_canister();
+        }
+
+        if !Self::_get_sibling_canisters().await?.contains(&sibling) {
+            return unknown_canister();
+        }
+
+        Ok(Self::_clear_relations_locally(principal))
+    }
+
+    // Method to check the social graph on this canister for inconsistencies and optionally repair them,
+    // asymmetric friendships are removed because a friendship needs both sides
+    pub async fn check_graph_consistency(repair: bool) -> GraphConsistencyReport {
+        let now = time();
+
+        // Principals without a profile on this canister are looked up on the other child canisters
+        let local_principals = Self::_get_local_principals();
+        let mut unknown_principals: Vec<Principal> = vec![];
+        for key in Self::_get_graph_keys() {
+            for principal in [key.owner, key.other] {
+                if !local_principals.contains(&principal)
+                    && !unknown_principals.contains(&principal)
+                {
+                    unknown_principals.push(principal);
+                }
+            }
+        }
+
+        if !unknown_principals.is_empty() {
+            match Self::_get_sibling_canisters().await {
+                Ok(siblings) => {
+                    for sibling in siblings {
+                        let result: Result<(Vec<ProfileResponse>,), _> = call::call(
+                            sibling,
+                            "get_profiles_by_user_principal",
+                            (unknown_principals.clone(),),
+                        )
+                        .await;
+
+                        match result {
+                            Ok((profiles,)) => unknown_principals.retain(|principal| {
+                                !profiles.iter().any(|p| &p.principal == principal)
+                            }),
+                            // When a canister can not be reached nothing can be marked as dangling
+                            Err(_) => unknown_principals.clear(),
+                        }
+                    }
+                }
+                // Without the other child canisters nothing can be marked as dangling
+                Err(_) => unknown_principals.clear(),
+            }
+        }
+
+        // The graph can change while waiting for the other canisters, so it is read again after the last call,
+        // nothing below awaits, so the repair acts on exactly the state that is checked here
+        let local_principals = Self::_get_local_principals();
+        unknown_principals.retain(|principal| !local_principals.contains(principal));
+
+        let asymmetric_friendships: Vec<(Principal, Principal)> = RELATIONS.with(|relations| {
+            relations
+                .borrow()
+                .iter()
+                .filter(|(key, relation)| {
+                    relation.kind == RelationType::Friend
+                        && !Self::_get_relation(key.other, key.owner)
+                            .is_some_and(|_relation| _relation.kind == RelationType::Friend)
+                })
+                .map(|(key, _)| (key.owner, key.other))
+                .collect()
+        });
+
+        let dangling_relations: Vec<(Principal, Principal)> = Self::_get_graph_keys()
+            .into_iter()
+            .filter(|key| {
+                unknown_principals.contains(&key.owner) || unknown_principals.contains(&key.other)
+            })
+            .map(|key| (key.owner, key.other))
+            .collect();
+
+        let redundant_friend_requests: Vec<u64> = FRIEND_REQUEST.with(|requests| {
+            requests
+                .borrow()
+                .iter()
+                .filter(|(_, request)| {
+                    Self::_get_relation(request.requested_by, request.to)
+                        .is_some_and(|relation| relation.kind == RelationType::Friend)
+                        || Self::_get_relation(request.to, request.requested_by)
+                            .is_some_and(|relation| relation.kind == RelationType::Friend)
+                })
+                .map(|(id, _)| id)
+                .collect()
+        });
+
+        if repair {
+            for (owner, other) in asymmetric_friendships.iter() {
+                Self::_remove_relation(*owner, *other, id(), "check_graph_consistency");
+            }
+
+            for (owner, other) in dangling_relations.iter() {
+                Self::_remove_relation(*owner, *other, id(), "check_graph_consistency");
+                Self::_remove_follow(*owner, *other);
+                MUTES.with(|mutes| {
+                    mutes.borrow_mut().remove(&RelationKey {
+                        owner: *owner,
+                        other: *other,
+                    })
+                });
+                Self::_remove_orphan_contact_note(*owner, *other);
+            }
+
+            FRIEND_REQUEST.with(|requests| {
+                let mut requests = requests.borrow_mut();
+                for id in redundant_friend_requests.iter() {
+                    requests.remove(id);
+                }
+            });
+        }
+
+        let report = GraphConsistencyReport {
+            checked_at: now,
+            asymmetric_friendships,
+            dangling_relations,
+            redundant_friend_requests,
+            repaired: repair,
+        };
+
+        GRAPH_CONSISTENCY_REPORT.with(|_report| *_report.borrow_mut() = Some(report.clone()));
+        report
+    }
+
+    // Method to get the principals of the profiles stored on this canister
+    fn _get_local_principals() -> Vec<Principal> {
+        ENTRIES
+            .with(Data::get_entries)
+            .into_iter()
+            .map(|(_, profile)| profile.principal)
+            .collect()
+    }
+
+    // Method to get the keys of all relations, follows and mutes stored on this canister
+    fn _get_graph_keys() -> Vec<RelationKey> {
+        let mut keys: Vec<RelationKey> =
+            RELATIONS.with(|relations| relations.borrow().iter().map(|(key, _)| key).collect());
+        keys.extend(FOLLOWING.with(|following| {
+            following
+                .borrow()
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<RelationKey>>()
+        }));
+        keys.extend(MUTES.with(|mutes| {
+            mutes
+                .borrow()
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<RelationKey>>()
+        }));
+        keys
+    }
+
+    // Method to get the report of the last social graph consistency check
+    pub fn get_graph_consistency_report() -> Option<GraphConsistencyReport> {
+        GRAPH_CONSISTENCY_REPORT.with(|report| report.borrow().clone())
+    }
+
+    // Method to get the other child canisters from the parent canister
+    async fn _get_sibling_canisters() -> Result<Vec<Principal>, ApiError> {
+        let parent = STABLE_DATA.with(|data| data.borrow().get().parent);
+
+        match get_canisters(parent).await {
+            Ok((canisters,)) => Ok(canisters
+                .into_iter()
+                .map(|canister| canister.principal)
+                .filter(|principal| principal != &id())
+                .collect()),
+            Err(err) => Err(api_error(
+                ApiErrorType::Unexpected,
+                "ICC_GET_CANISTERS_FAILED",
+                err.1.as_str(),
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "get_sibling_canisters",
+                None,
+            )),
+        }
+    }
+
+    // Method to remove the friends, blocks and follows of a principal stored on this canister,
+    // friendships are removed on both sides, blocks by other principals are kept
+    fn _clear_relations_locally(principal: Principal) -> ClearRelationsSummary {
+        let mut summary = ClearRelationsSummary::default();
+
+        for (other, relation) in Self::_get_relations(principal) {
+            Self::_remove_relation(principal, other, principal, "clear_relations");
+            match relation.kind {
+                RelationType::Blocked => summary.blocked.push(other),
+                _ => summary.friends.push(other),
+            }
+        }
+
+        let counterparts: Vec<RelationKey> = RELATIONS.with(|relations| {
+            relations
+                .borrow()
+                .iter()
+                .filter(|(key, relation)| {
+                    key.other == principal && relation.kind == RelationType::Friend
+                })
+                .map(|(key, _)| key)
+                .collect()
+        });
+        for key in counterparts {
+            Self::_remove_relation(key.owner, principal, principal, "clear_relations");
+            if !summary.friends.contains(&key.owner) {
+                summary.friends.push(key.owner);
+            }
+        }
+
+        for (other, _) in Self::_get_relations_by_type(principal, RelationType::Follow) {
+            Self::_remove_follow(principal, other);
+            summary.following.push(other);
+        }
+
+        for (other, _) in Self::_get_relations_by_type(principal, RelationType::Follower) {
+            Self::_remove_follow(other, principal);
+            summary.followers.push(other);
+        }
+
+        summary
+    }
+
+    pub fn decline_friend_request(caller: Principal, id: u64) -> Result<bool, ApiError> {
+        FRIEND_REQUEST.with(|r| {
+            let mut requests = r.borrow_mut();
+
+            if let Some(request) = requests.get(&id) {
+                if request.to == caller {
+                    let display_name = Self::_get_profile_from_caller(caller)
+                        .map_or("unknown".to_string(), |(_, p)| p.display_name);
+
+                    let metadata = json!({
+                        "declinedBy": display_name,
+                        "declinedByPrincipal": caller.to_string(),
+                    });
+
+                    if !Self::_is_muted_by(request.requested_by, caller)
+                        && Self::_is_notification_enabled(request.requested_by, |preferences| {
+                            &preferences.friend_request_declined
+                        })
+                    {
+                        Self::_notify(
+                            request.requested_by,
+                            SendNotificationData {
+                                data: NotificationType::FriendRequest(
+                                    FriendRequestNotificationData {
+                                        friend_request_id: id,
+                                        from: request.requested_by,
+                                        to: request.to,
+                                        accepted: Some(false),
+                                    },
+                                ),
+                                receivers: vec![request.requested_by],
+                            },
+                            metadata.to_string(),
+                        );
+                    }
+
+                    requests.remove(&id);
+                    return Ok(true);
+                }
+            }
+
+            Err(Self::_friend_request_not_found_error(
+                "decline_friend_request",
+                id,
+            ))
+        })
+    }
+
+    pub fn remove_friend_request(caller: Principal, id: u64) -> Result<bool, ApiError> {
+        FRIEND_REQUEST.with(|r| {
+            let mut requests = r.borrow_mut();
+
+            if let Some(request) = requests.get(&id) {
+                if request.requested_by == caller {
+                    requests.remove(&id);
+                    return Ok(true);
+                }
+            }
+
+            Err(Self::_friend_request_not_found_error(
+                "remove_friend_request",
+                id,
+            ))
+        })
+    }
+
+    fn _friend_request_not_found_error(method_name: &str, id: u64) -> ApiError {
+        api_error(
+            ApiErrorType::NotFound,
+            "REQUEST_NOT_FOUND",
+            "Friend request not found",
+            STABLE_DATA
+                .with(|data| Data::get_name(data.borrow().get()))
+                .as_str(),
+            method_name,
+            Some(vec![format!("id - {:?}", &id)]),
+        )
+    }
+
+    pub fn block_user(caller: Principal, to_block: Principal) -> Result<ProfileResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller.to_string()),
+            format!("relation_identifier - {:?}", &to_block.to_string()),
         ]);
 
         // get the profile from the data store
@@ -1503,46 +3188,3625 @@ impl Store {
             // If the profile does not exist, return an error
             None => Err(Self::_profile_not_found_error("block_user", inputs)),
             // If the profile exists, continue
-            Some((_identifier, mut _profile)) => {
-                // Add the relation to the profile, if existing it will be overwritten
-                _profile.relations.remove(&to_unblock);
+            Some((_identifier, _profile)) => {
+                // Add the relation, if existing it will be overwritten
+                Self::_set_relation(
+                    caller,
+                    to_block,
+                    RelationType::Blocked,
+                    caller,
+                    caller,
+                    "block_user",
+                );
+                // Blocking removes the friendship on the other side, unless that is a block as well
+                if let Some(relation) = Self::_get_relation(to_block, caller) {
+                    if relation.kind == RelationType::Friend {
+                        Self::_remove_relation(to_block, caller, caller, "block_user");
+                    }
+                }
+                // Blocking removes the follows in both directions
+                Self::_remove_follow(caller, to_block);
+                Self::_remove_follow(to_block, caller);
+                // Blocking cancels the pending friend requests in both directions
+                FRIEND_REQUEST.with(|requests| {
+                    let mut requests = requests.borrow_mut();
+                    let ids: Vec<u64> = requests
+                        .iter()
+                        .filter(|(_, r)| {
+                            (r.requested_by == caller && r.to == to_block)
+                                || (r.requested_by == to_block && r.to == caller)
+                        })
+                        .map(|(id, _)| id)
+                        .collect();
+                    for id in ids {
+                        requests.remove(&id);
+                    }
+                });
+                Ok(Self::_map_profile_to_profile_response(
+                    _identifier.to_string(),
+                    _profile,
+                ))
+            }
+        }
+    }
 
-                // Update the profile in the data store
-                ENTRIES
-                    .with(|entries| {
-                        STABLE_DATA
-                            .with(|data| Data::update_entry(data, entries, _identifier, _profile))
-                    })
-                    .map_or_else(
-                        |err| Err(err),
-                        |result| {
-                            Ok(Self::_map_profile_to_profile_response(
-                                result.0.to_string(),
-                                result.1,
-                            ))
-                        },
-                    )
+    pub fn unblock_user(
+        caller: Principal,
+        to_unblock: Principal,
+    ) -> Result<ProfileResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller.to_string()),
+            format!("relation_identifier - {:?}", &to_unblock.to_string()),
+        ]);
+
+        // get the profile from the data store
+        match Self::_get_profile_from_caller(caller) {
+            // If the profile does not exist, return an error
+            None => Err(Self::_profile_not_found_error("block_user", inputs)),
+            // If the profile exists, continue
+            Some((_identifier, _profile)) => {
+                // Remove the blocked relation, other relations are kept
+                if let Some(relation) = Self::_get_relation(caller, to_unblock) {
+                    if relation.kind == RelationType::Blocked {
+                        Self::_remove_relation(caller, to_unblock, caller, "unblock_user");
+                    }
+                }
+                Ok(Self::_map_profile_to_profile_response(
+                    _identifier.to_string(),
+                    _profile,
+                ))
             }
         }
     }
 
-    fn get_environment() -> Option<Environment> {
-        let canister_id = id().to_string();
-        if canister_id == "4vy4w-gaaaa-aaaap-aa4pa-cai".to_string() {
-            return Some(Environment::Production);
+    // Method to follow a principal, unlike a friendship a follow does not need to be accepted
+    pub fn follow(caller: Principal, to_follow: Principal) -> Result<bool, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller.to_string()),
+            format!("to_follow - {:?}", &to_follow.to_string()),
+        ]);
+
+        if Self::_get_profile_from_caller(caller).is_none() {
+            return Err(Self::_profile_not_found_error("follow", inputs));
+        }
+
+        if caller == to_follow {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "CANNOT_FOLLOW_SELF",
+                "You can not follow yourself",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "follow",
+                inputs,
+            ));
+        }
+
+        // Only principals with a profile on this canister can be followed, the follower and followers
+        // stores are local to the child canister
+        if Self::_get_profile_from_caller(to_follow).is_none() {
+            return Err(api_error(
+                ApiErrorType::NotFound,
+                "PROFILE_NOT_ON_CANISTER",
+                "The principal to follow has no profile on this canister",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "follow",
+                inputs,
+            ));
         }
-        if canister_id == "5ycyv-iiaaa-aaaap-abgia-cai" {
-            return Some(Environment::Staging);
+
+        if Self::_is_blocked_by(to_follow, caller) || Self::_is_blocked_by(caller, to_follow) {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "BLOCKED",
+                "You can not follow this user",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "follow",
+                inputs,
+            ));
         }
-        if canister_id == "crorp-uaaaa-aaaap-abqwq-cai" {
-            return Some(Environment::Development);
-        } else {
-            return None;
+
+        let key = RelationKey {
+            owner: caller,
+            other: to_follow,
+        };
+
+        if FOLLOWING.with(|following| following.borrow().contains_key(&key)) {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "ALREADY_FOLLOWING",
+                "You already follow this user",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "follow",
+                inputs,
+            ));
         }
-    }
 
-    fn send_notification() -> Notification {
-        let environment = Self::get_environment().expect("Environment not found");
-        Notification::new(environment)
+        let relation = Relation {
+            kind: RelationType::Follow,
+            since: time(),
+            initiated_by: caller,
+        };
+
+        FOLLOWING.with(|following| following.borrow_mut().insert(key, relation.clone()));
+        FOLLOWERS.with(|followers| {
+            followers.borrow_mut().insert(
+                RelationKey {
+                    owner: to_follow,
+                    other: caller,
+                },
+                relation,
+            )
+        });
+
+        let display_name = Self::_get_profile_from_caller(caller)
+            .map_or("unknown".to_string(), |(_, p)| p.display_name);
+
+        let metadata = json!({
+            "followedBy": display_name,
+            "followedByPrincipal": caller.to_string(),
+        });
+
+        if !Self::_is_muted_by(to_follow, caller)
+            && Self::_is_notification_enabled(to_follow, |preferences| &preferences.new_follower)
+        {
+            Self::_notify(
+                caller,
+                SendNotificationData {
+                    data: NotificationType::Misc("follow".to_string()),
+                    receivers: vec![to_follow],
+                },
+                metadata.to_string(),
+            );
+        }
+
+        Ok(true)
+    }
+
+    // Method to stop following a principal
+    pub fn unfollow(caller: Principal, to_unfollow: Principal) -> Result<bool, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller.to_string()),
+            format!("to_unfollow - {:?}", &to_unfollow.to_string()),
+        ]);
+
+        match Self::_remove_follow(caller, to_unfollow) {
+            true => Ok(true),
+            false => Err(api_error(
+                ApiErrorType::NotFound,
+                "NOT_FOLLOWING",
+                "You do not follow this user",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "unfollow",
+                inputs,
+            )),
+        }
+    }
+
+    // Method to mute a principal, the muted principal is not notified and can not see the mute
+    pub fn mute(caller: Principal, to_mute: Principal) -> Result<bool, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller.to_string()),
+            format!("to_mute - {:?}", &to_mute.to_string()),
+        ]);
+
+        if Self::_get_profile_from_caller(caller).is_none() {
+            return Err(Self::_profile_not_found_error("mute", inputs));
+        }
+
+        if caller == to_mute {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "CANNOT_MUTE_SELF",
+                "You can not mute yourself",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "mute",
+                inputs,
+            ));
+        }
+
+        // Muting an already muted principal keeps the original mute
+        if !Self::_is_muted_by(caller, to_mute) {
+            MUTES.with(|mutes| {
+                mutes.borrow_mut().insert(
+                    RelationKey {
+                        owner: caller,
+                        other: to_mute,
+                    },
+                    Relation {
+                        kind: RelationType::Muted,
+                        since: time(),
+                        initiated_by: caller,
+                    },
+                )
+            });
+        }
+
+        Ok(true)
+    }
+
+    // Method to unmute a principal
+    pub fn unmute(caller: Principal, to_unmute: Principal) -> Result<bool, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller.to_string()),
+            format!("to_unmute - {:?}", &to_unmute.to_string()),
+        ]);
+
+        match MUTES.with(|mutes| {
+            mutes.borrow_mut().remove(&RelationKey {
+                owner: caller,
+                other: to_unmute,
+            })
+        }) {
+            Some(_) => {
+                Self::_remove_orphan_contact_note(caller, to_unmute);
+                Ok(true)
+            }
+            None => Err(api_error(
+                ApiErrorType::NotFound,
+                "NOT_MUTED",
+                "You did not mute this user",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "unmute",
+                inputs,
+            )),
+        }
+    }
+
+    // Method to add a contact group, the members have to be friends of the caller
+    pub fn add_contact_group(
+        caller: Principal,
+        post_contact_group: PostContactGroup,
+    ) -> Result<ContactGroupResponse, ApiError> {
+        Self::_validate_contact_group(caller, None, &post_contact_group, "add_contact_group")?;
+
+        let now = time();
+        let contact_group = ContactGroup {
+            owner: caller,
+            name: post_contact_group.name,
+            members: post_contact_group.members,
+            created_on: now,
+            updated_on: now,
+        };
+
+        let id = Self::_next_contact_group_id();
+        CONTACT_GROUPS.with(|groups| groups.borrow_mut().insert(id, contact_group.clone()));
+
+        Ok(Self::_map_contact_group_to_response(id, contact_group))
+    }
+
+    // Method to change the name and members of a contact group of the caller
+    pub fn edit_contact_group(
+        caller: Principal,
+        id: u64,
+        post_contact_group: PostContactGroup,
+    ) -> Result<ContactGroupResponse, ApiError> {
+        let mut contact_group = Self::_get_contact_group(caller, id, "edit_contact_group")?;
+        Self::_validate_contact_group(caller, Some(id), &post_contact_group, "edit_contact_group")?;
+
+        contact_group.name = post_contact_group.name;
+        contact_group.members = post_contact_group.members;
+        contact_group.updated_on = time();
+
+        CONTACT_GROUPS.with(|groups| groups.borrow_mut().insert(id, contact_group.clone()));
+        Ok(Self::_map_contact_group_to_response(id, contact_group))
+    }
+
+    // Method to delete a contact group of the caller, fields that are visible to the group become visible to no one
+    pub fn delete_contact_group(caller: Principal, id: u64) -> Result<bool, ApiError> {
+        Self::_get_contact_group(caller, id, "delete_contact_group")?;
+        CONTACT_GROUPS.with(|groups| groups.borrow_mut().remove(&id));
+
+        FIELD_VISIBILITY.with(|visibility| {
+            let mut visibility = visibility.borrow_mut();
+            if let Some(mut _visibility) = visibility.get(&caller.to_string()) {
+                for field in _visibility.fields.iter_mut() {
+                    if field.audience == Audience::ContactGroup(id) {
+                        field.audience = Audience::OnlyMe;
+                    }
+                }
+                visibility.insert(caller.to_string(), _visibility);
+            }
+        });
+        Ok(true)
+    }
+
+    // Method to get a new contact group id, the ids handed out before the counter existed are skipped,
+    // including the ids of deleted groups that are still the audience of a field
+    fn _next_contact_group_id() -> u64 {
+        let next_key = |last_key: Option<u64>| last_key.map_or(0, |key| key.saturating_add(1));
+
+        CONTACT_GROUPS_NEXT_ID.with(|next_id| {
+            let mut next_id = next_id.borrow_mut();
+            let mut id = *next_id.get();
+            if id == 0 {
+                let in_use = next_key(
+                    CONTACT_GROUPS.with(|groups| groups.borrow().last_key_value().map(|(k, _)| k)),
+                );
+                let in_audience = next_key(FIELD_VISIBILITY.with(|visibility| {
+                    visibility
+                        .borrow()
+                        .iter()
+                        .flat_map(|(_, _visibility)| _visibility.fields)
+                        .filter_map(|field| match field.audience {
+                            Audience::ContactGroup(_id) => Some(_id),
+                            _ => None,
+                        })
+                        .max()
+                }));
+                id = in_use.max(in_audience);
+            }
+            let _ = next_id.set(id.saturating_add(1));
+            id
+        })
+    }
+
+    // Method to get the contact groups of the caller
+    pub fn get_contact_groups(caller: Principal) -> Vec<ContactGroupResponse> {
+        CONTACT_GROUPS.with(|groups| {
+            groups
+                .borrow()
+                .iter()
+                .filter(|(_, group)| group.owner == caller)
+                .map(|(id, group)| Self::_map_contact_group_to_response(id, group))
+                .collect()
+        })
+    }
+
+    // Method to get who can see the fields of the profile of a principal
+    pub fn get_field_visibility(principal: Principal) -> Vec<FieldVisibility> {
+        FIELD_VISIBILITY
+            .with(|visibility| visibility.borrow().get(&principal.to_string()))
+            .unwrap_or_default()
+            .fields
+    }
+
+    // Method to set who can see the fields of the profile of the caller, fields that are not passed are public
+    pub fn set_field_visibility(
+        caller: Principal,
+        fields: Vec<FieldVisibility>,
+    ) -> Result<Vec<FieldVisibility>, ApiError> {
+        for visibility in fields.iter() {
+            if let Audience::ContactGroup(id) = visibility.audience {
+                Self::_get_contact_group(caller, id, "set_field_visibility")?;
+            }
+        }
+
+        // The last visibility of a field wins
+        let mut unique_fields: Vec<FieldVisibility> = vec![];
+        for visibility in fields.into_iter().rev() {
+            if !unique_fields.iter().any(|f| f.field == visibility.field) {
+                unique_fields.push(visibility);
+            }
+        }
+        unique_fields.reverse();
+
+        FIELD_VISIBILITY.with(|visibility| {
+            visibility.borrow_mut().insert(
+                caller.to_string(),
+                ProfileFieldVisibility {
+                    fields: unique_fields.clone(),
+                },
+            )
+        });
+        Ok(unique_fields)
+    }
+
+    // Method to check if a viewer is allowed to see a field of the profile of the owner
+    fn _is_field_visible(owner: Principal, viewer: Principal, field: ProfileField) -> bool {
+        viewer == owner
+            || Self::get_field_visibility(owner)
+                .iter()
+                .filter(|visibility| visibility.field == field)
+                .all(|visibility| Self::_is_in_audience(owner, viewer, &visibility.audience))
+    }
+
+    // Method to check if a viewer is part of an audience of a profile
+    fn _is_in_audience(owner: Principal, viewer: Principal, audience: &Audience) -> bool {
+        match audience {
+            Audience::Public => true,
+            Audience::Friends => Self::_get_relation(owner, viewer)
+                .is_some_and(|relation| relation.kind == RelationType::Friend),
+            Audience::ContactGroup(id) => CONTACT_GROUPS
+                .with(|groups| groups.borrow().get(id))
+                .is_some_and(|group| group.owner == owner && group.members.contains(&viewer)),
+            Audience::OnlyMe => false,
+        }
+    }
+
+    fn _get_contact_group(
+        caller: Principal,
+        id: u64,
+        method_name: &str,
+    ) -> Result<ContactGroup, ApiError> {
+        match CONTACT_GROUPS.with(|groups| groups.borrow().get(&id)) {
+            Some(group) if group.owner == caller => Ok(group),
+            _ => Err(api_error(
+                ApiErrorType::NotFound,
+                "CONTACT_GROUP_NOT_FOUND",
+                "Contact group not found",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                method_name,
+                Some(vec![format!("id - {:?}", &id)]),
+            )),
+        }
+    }
+
+    fn _validate_contact_group(
+        caller: Principal,
+        id: Option<u64>,
+        post_contact_group: &PostContactGroup,
+        method_name: &str,
+    ) -> Result<(), ApiError> {
+        let inputs = Some(vec![format!("name - {:?}", &post_contact_group.name)]);
+
+        if Self::_get_profile_from_caller(caller).is_none() {
+            return Err(Self::_profile_not_found_error(method_name, inputs));
+        }
+
+        validate_post_contact_group(post_contact_group.clone())?;
+
+        let name_exists = CONTACT_GROUPS.with(|groups| {
+            groups.borrow().iter().any(|(_id, group)| {
+                group.owner == caller
+                    && Some(_id) != id
+                    && group.name.to_lowercase() == post_contact_group.name.to_lowercase()
+            })
+        });
+
+        if name_exists {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "CONTACT_GROUP_EXISTS",
+                "You already have a contact group with this name",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                method_name,
+                inputs,
+            ));
+        }
+
+        let friends = Self::get_relations(caller, RelationType::Friend);
+        let not_friends: Vec<String> = post_contact_group
+            .members
+            .iter()
+            .filter(|member| !friends.contains(member))
+            .map(|member| member.to_string())
+            .collect();
+
+        if !not_friends.is_empty() {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "NOT_A_FRIEND",
+                format!(
+                    "Only friends can be added to a contact group, not: {}",
+                    not_friends.join(", ")
+                )
+                .as_str(),
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                method_name,
+                inputs,
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Method to remove a principal from the contact groups of an owner, used when they are no longer friends
+    fn _remove_from_contact_groups(owner: Principal, member: Principal) {
+        CONTACT_GROUPS.with(|groups| {
+            let mut groups = groups.borrow_mut();
+            let changed: Vec<(u64, ContactGroup)> = groups
+                .iter()
+                .filter(|(_, group)| group.owner == owner && group.members.contains(&member))
+                .collect();
+            for (id, mut group) in changed {
+                group.members.retain(|_member| _member != &member);
+                group.updated_on = time();
+                groups.insert(id, group);
+            }
+        });
+    }
+
+    fn _map_contact_group_to_response(id: u64, group: ContactGroup) -> ContactGroupResponse {
+        ContactGroupResponse {
+            id,
+            name: group.name,
+            members: group.members,
+            created_on: group.created_on,
+            updated_on: group.updated_on,
+        }
+    }
+
+    // Method to set the private note and tags of the caller about one of its relations
+    pub fn set_contact_note(
+        caller: Principal,
+        principal: Principal,
+        post_contact_note: PostContactNote,
+    ) -> Result<ContactNoteResponse, ApiError> {
+        let inputs = Some(vec![format!("principal - {:?}", &principal.to_string())]);
+
+        if Self::_get_profile_from_caller(caller).is_none() {
+            return Err(Self::_profile_not_found_error("set_contact_note", inputs));
+        }
+
+        validate_post_contact_note(post_contact_note.clone())?;
+
+        if !Self::_has_any_relation(caller, principal) {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "NOT_IN_RELATIONS",
+                "Notes can only be added to principals in your relations",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "set_contact_note",
+                inputs,
+            ));
+        }
+
+        let key = RelationKey {
+            owner: caller,
+            other: principal,
+        };
+        let now = time();
+        let created_on = CONTACT_NOTES
+            .with(|notes| notes.borrow().get(&key))
+            .map_or(now, |note| note.created_on);
+
+        let contact_note = ContactNote {
+            note: post_contact_note.note,
+            tags: post_contact_note.tags,
+            created_on,
+            updated_on: now,
+        };
+        CONTACT_NOTES.with(|notes| notes.borrow_mut().insert(key, contact_note.clone()));
+
+        Ok(Self::_map_contact_note_to_response(principal, contact_note))
+    }
+
+    // Method to get the private note of the caller about a principal
+    pub fn get_contact_note(
+        caller: Principal,
+        principal: Principal,
+    ) -> Result<ContactNoteResponse, ApiError> {
+        CONTACT_NOTES
+            .with(|notes| {
+                notes.borrow().get(&RelationKey {
+                    owner: caller,
+                    other: principal,
+                })
+            })
+            .map(|note| Self::_map_contact_note_to_response(principal, note))
+            .ok_or_else(|| Self::_contact_note_not_found_error("get_contact_note", principal))
+    }
+
+    // Method to get all private notes of the caller
+    pub fn get_contact_notes(caller: Principal) -> Vec<ContactNoteResponse> {
+        CONTACT_NOTES.with(|notes| {
+            notes
+                .borrow()
+                .range(RelationKey::first_of(caller)..)
+                .take_while(|(key, _)| key.owner == caller)
+                .map(|(key, note)| Self::_map_contact_note_to_response(key.other, note))
+                .collect()
+        })
+    }
+
+    // Method to remove the private note of the caller about a principal
+    pub fn clear_contact_note(caller: Principal, principal: Principal) -> Result<bool, ApiError> {
+        CONTACT_NOTES
+            .with(|notes| {
+                notes.borrow_mut().remove(&RelationKey {
+                    owner: caller,
+                    other: principal,
+                })
+            })
+            .map(|_| true)
+            .ok_or_else(|| Self::_contact_note_not_found_error("clear_contact_note", principal))
+    }
+
+    fn _contact_note_not_found_error(method_name: &str, principal: Principal) -> ApiError {
+        api_error(
+            ApiErrorType::NotFound,
+            "CONTACT_NOTE_NOT_FOUND",
+            "Contact note not found",
+            STABLE_DATA
+                .with(|data| Data::get_name(data.borrow().get()))
+                .as_str(),
+            method_name,
+            Some(vec![format!("principal - {:?}", &principal.to_string())]),
+        )
+    }
+
+    fn _map_contact_note_to_response(
+        principal: Principal,
+        note: ContactNote,
+    ) -> ContactNoteResponse {
+        ContactNoteResponse {
+            principal,
+            note: note.note,
+            tags: note.tags,
+            created_on: note.created_on,
+            updated_on: note.updated_on,
+        }
+    }
+
+    // Method to delete the profile of the caller, either immediately or after the grace period
+    pub fn delete_profile(
+        caller: Principal,
+        immediately: bool,
+    ) -> Result<ProfileDeletion, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller),
+            format!("immediately - {:?}", &immediately),
+        ]);
+
+        if Self::_get_profile_from_caller(caller).is_none() {
+            return Err(Self::_profile_not_found_error("delete_profile", inputs));
+        }
+
+        if immediately {
+            // A deletion that was already scheduled keeps the moment it was requested
+            let requested_on =
+                Self::get_profile_deletion(caller).map_or(time(), |deletion| deletion.requested_on);
+            Self::_erase_profile(caller);
+            return Ok(ProfileDeletion {
+                requested_on,
+                delete_after: time(),
+                deleted_on: Some(time()),
+            });
+        }
+
+        // Keep an already scheduled deletion so the grace period can not be extended
+        let deletion = PROFILE_DELETIONS.with(|deletions| {
+            let mut deletions = deletions.borrow_mut();
+            match deletions.get(&caller.to_string()) {
+                Some(_deletion) => _deletion,
+                None => {
+                    let deletion = ProfileDeletion {
+                        requested_on: time(),
+                        delete_after: time() + PROFILE_DELETION_GRACE_PERIOD,
+                        deleted_on: None,
+                    };
+                    deletions.insert(caller.to_string(), deletion.clone());
+                    deletion
+                }
+            }
+        });
+
+        Ok(deletion)
+    }
+
+    // Method to cancel a scheduled deletion of the profile of the caller
+    pub fn cancel_profile_deletion(caller: Principal) -> Result<bool, ApiError> {
+        match PROFILE_DELETIONS.with(|deletions| deletions.borrow_mut().remove(&caller.to_string()))
+        {
+            None => Err(api_error(
+                ApiErrorType::NotFound,
+                "DELETION_NOT_FOUND",
+                "There is no scheduled deletion for this profile",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "cancel_profile_deletion",
+                Some(vec![format!("principal - {:?}", &caller)]),
+            )),
+            Some(_) => Ok(true),
+        }
+    }
+
+    // Method to get the scheduled deletion of the profile of the caller
+    pub fn get_profile_deletion(caller: Principal) -> Option<ProfileDeletion> {
+        PROFILE_DELETIONS.with(|deletions| deletions.borrow().get(&caller.to_string()))
+    }
+
+    // Method to erase the profiles of which the grace period has passed, triggered by a timer
+    pub fn process_profile_deletions() {
+        let due: Vec<Principal> = PROFILE_DELETIONS.with(|deletions| {
+            deletions
+                .borrow()
+                .iter()
+                .filter(|(_, deletion)| deletion.delete_after <= time())
+                .filter_map(|(principal, _)| Principal::from_text(principal).ok())
+                .collect()
+        });
+
+        for principal in due {
+            Self::_erase_profile(principal);
+        }
+    }
+
+    // Method to remove all personal data of a profile and leave a tombstone for its identifier
+    fn _erase_profile(principal: Principal) {
+        PROFILE_DELETIONS.with(|deletions| deletions.borrow_mut().remove(&principal.to_string()));
+        RELATIONS_VISIBILITY
+            .with(|visibility| visibility.borrow_mut().remove(&principal.to_string()));
+        FIELD_VISIBILITY.with(|visibility| visibility.borrow_mut().remove(&principal.to_string()));
+        FRIEND_REQUESTS_SENT.with(|sent| sent.borrow_mut().remove(&principal.to_string()));
+        CLEAR_RELATIONS_NONCES.with(|nonces| nonces.borrow_mut().remove(&principal));
+
+        // Remove the notifications that were sent by or to the profile, these hold its display name and messages
+        let text = principal.to_string();
+        let is_outbox_match = |notification: &OutboxNotification| {
+            notification.sender == Some(principal)
+                || notification.data.contains(&text)
+                || notification.metadata.contains(&text)
+        };
+        for outbox in [&NOTIFICATION_OUTBOX, &NOTIFICATION_DEAD_LETTERS] {
+            outbox.with(|outbox| {
+                let mut outbox = outbox.borrow_mut();
+                let ids: Vec<u64> = outbox
+                    .iter()
+                    .filter(|(_, notification)| is_outbox_match(notification))
+                    .map(|(id, _)| id)
+                    .collect();
+                for id in ids {
+                    outbox.remove(&id);
+                }
+            });
+        }
+        LOCAL_NOTIFICATIONS.with(|notifications| {
+            let mut notifications = notifications.borrow_mut();
+            let ids: Vec<u64> = notifications
+                .iter()
+                .filter(|(_, notification)| {
+                    notification.sender == Some(principal)
+                        || notification.receivers.contains(&principal)
+                        || notification.metadata.contains(&text)
+                })
+                .map(|(id, _)| id)
+                .collect();
+            for id in ids {
+                notifications.remove(&id);
+            }
+        });
+
+        let identifier = match Self::_get_profile_from_caller(principal) {
+            None => return,
+            Some((_identifier, _)) => _identifier,
+        };
+
+        // Remove all relations of the profile and the relations other profiles have with it
+        RELATIONS.with(|relations| {
+            let mut relations = relations.borrow_mut();
+            let keys: Vec<RelationKey> = relations
+                .iter()
+                .filter(|(key, _)| key.owner == principal || key.other == principal)
+                .map(|(key, _)| key)
+                .collect();
+            for key in keys {
+                relations.remove(&key);
+            }
+        });
+        for (other, _) in Self::_get_relations_by_type(principal, RelationType::Follow) {
+            Self::_remove_follow(principal, other);
+        }
+        for (other, _) in Self::_get_relations_by_type(principal, RelationType::Follower) {
+            Self::_remove_follow(other, principal);
+        }
+        MUTES.with(|mutes| {
+            let mut mutes = mutes.borrow_mut();
+            let keys: Vec<RelationKey> = mutes
+                .iter()
+                .filter(|(key, _)| key.owner == principal || key.other == principal)
+                .map(|(key, _)| key)
+                .collect();
+            for key in keys {
+                mutes.remove(&key);
+            }
+        });
+
+        // Remove the notes of the profile and the notes other profiles have about it
+        CONTACT_NOTES.with(|notes| {
+            let mut notes = notes.borrow_mut();
+            let keys: Vec<RelationKey> = notes
+                .iter()
+                .filter(|(key, _)| key.owner == principal || key.other == principal)
+                .map(|(key, _)| key)
+                .collect();
+            for key in keys {
+                notes.remove(&key);
+            }
+        });
+
+        // Remove the contact groups of the profile and its membership of other contact groups
+        CONTACT_GROUPS.with(|groups| {
+            let mut groups = groups.borrow_mut();
+            let changed: Vec<(u64, ContactGroup)> = groups
+                .iter()
+                .filter(|(_, group)| group.owner == principal || group.members.contains(&principal))
+                .collect();
+            for (id, mut group) in changed {
+                if group.owner == principal {
+                    groups.remove(&id);
+                } else {
+                    group.members.retain(|member| member != &principal);
+                    groups.insert(id, group);
+                }
+            }
+        });
+
+        // Remove all friend requests send by or to the profile
+        FRIEND_REQUEST.with(|requests| {
+            let mut requests = requests.borrow_mut();
+            let ids: Vec<u64> = requests
+                .iter()
+                .filter(|(_, r)| r.requested_by == principal || r.to == principal)
+                .map(|(id, _)| id)
+                .collect();
+            for id in ids {
+                requests.remove(&id);
+            }
+        });
+
+        // Release the old usernames of the profile
+        let usernames = USERNAME_HISTORY_BY_PROFILE
+            .with(|history| history.borrow_mut().remove(&principal.to_string()))
+            .map_or(vec![], |history| history.usernames);
+        USERNAME_HISTORY.with(|history| {
+            let mut history = history.borrow_mut();
+            for username in usernames {
+                // Only remove the reservation if it was not taken over by another profile since
+                if history
+                    .get(&username)
+                    .is_some_and(|change| change.principal == principal)
+                {
+                    history.remove(&username);
+                }
+            }
+        });
+
+        // Remove the change log of the profile
+        PROFILE_CHANGES.with(|changes| {
+            let mut changes = changes.borrow_mut();
+            let ids: Vec<u64> = changes
+                .iter()
+                .filter(|(_, change)| change.identifier == identifier)
+                .map(|(id, _)| id)
+                .collect();
+            for id in ids {
+                changes.remove(&id);
+            }
+        });
+
+        // Remove the profile itself, which also releases the username and email
+        // the tombstone is kept by identifier and by principal, so lookups by either resolve to the deletion
+        ENTRIES.with(|entries| Data::remove_entry(entries, &identifier));
+        TOMBSTONES.with(|tombstones| {
+            let mut tombstones = tombstones.borrow_mut();
+            for key in [identifier, principal] {
+                tombstones.insert(key.to_string(), ProfileTombstone { deleted_on: time() });
+            }
+        });
+    }
+
+    // Method to export all data stored about the caller as a versioned JSON document,
+    // the document is chunked when it is larger than the max bytes per chunk ` (bytes, (chunk, last_chunk)) `
+    // the document is built from the current state on every call, so clients fetch all chunks within one
+    // `DATA_EXPORT_WINDOW` and start over when the combined chunks are not a valid document
+    pub fn export_my_data(
+        caller: Principal,
+        chunk: usize,
+        max_bytes_per_chunk: usize,
+    ) -> Result<(Vec<u8>, (usize, usize)), ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller),
+            format!("chunk - {:?}", &chunk),
+            format!("max_bytes_per_chunk - {:?}", &max_bytes_per_chunk),
+        ]);
+
+        let (identifier, profile) = match Self::_get_profile_from_caller(caller) {
+            None => return Err(Self::_profile_not_found_error("export_my_data", inputs)),
+            Some(_profile) => _profile,
+        };
+
+        let profiles = ENTRIES.with(Data::get_entries);
+
+        // Resolve the other party of every relation to the profile on this canister, if any
+        let relations: Vec<serde_json::Value> = Self::_get_relations(caller)
+            .into_iter()
+            .chain(Self::_get_relations_by_type(caller, RelationType::Follow))
+            .chain(Self::_get_relations_by_type(caller, RelationType::Follower))
+            .collect::<Vec<(Principal, Relation)>>()
+            .iter()
+            .map(|(_principal, _relation)| {
+                let other = profiles
+                    .iter()
+                    .find(|(_, _profile)| &_profile.principal == _principal);
+                json!({
+                    "principal": _principal.to_string(),
+                    "relation_type": _relation.kind.to_string(),
+                    "since": _relation.since,
+                    "initiated_by": _relation.initiated_by.to_string(),
+                    "identifier": other.map(|(_identifier, _)| _identifier.clone()),
+                    "username": other.map(|(_, _profile)| _profile.username.clone()),
+                    "display_name": other.map(|(_, _profile)| _profile.display_name.clone()),
+                })
+            })
+            .collect();
+
+        let (incoming, outgoing): (Vec<serde_json::Value>, Vec<serde_json::Value>) = FRIEND_REQUEST
+            .with(|requests| {
+                let requests = requests.borrow();
+                let map_request = |id: u64, request: &FriendRequest| {
+                    json!({
+                        "id": id,
+                        "requested_by": request.requested_by.to_string(),
+                        "to": request.to.to_string(),
+                        "message": request.message,
+                        "created_at": request.created_at,
+                    })
+                };
+                (
+                    requests
+                        .iter()
+                        .filter(|(_, request)| request.to == caller)
+                        .map(|(id, request)| map_request(id, &request))
+                        .collect(),
+                    requests
+                        .iter()
+                        .filter(|(_, request)| request.requested_by == caller)
+                        .map(|(id, request)| map_request(id, &request))
+                        .collect(),
+                )
+            });
+
+        let contact_groups: Vec<serde_json::Value> = Self::get_contact_groups(caller)
+            .into_iter()
+            .map(|group| {
+                json!({
+                    "id": group.id,
+                    "name": group.name,
+                    "members": group.members.iter().map(|m| m.to_string()).collect::<Vec<String>>(),
+                    "created_on": group.created_on,
+                    "updated_on": group.updated_on,
+                })
+            })
+            .collect();
+
+        let contact_notes: Vec<serde_json::Value> = Self::get_contact_notes(caller)
+            .into_iter()
+            .map(|note| {
+                json!({
+                    "principal": note.principal.to_string(),
+                    "note": note.note,
+                    "tags": note.tags,
+                    "created_on": note.created_on,
+                    "updated_on": note.updated_on,
+                })
+            })
+            .collect();
+
+        let field_visibility: Vec<serde_json::Value> = Self::get_field_visibility(caller)
+            .iter()
+            .map(|visibility| {
+                json!({
+                    "field": format!("{:?}", visibility.field),
+                    "audience": format!("{:?}", visibility.audience),
+                })
+            })
+            .collect();
+
+        let mutes: Vec<serde_json::Value> =
+            Self::_get_relations_by_type(caller, RelationType::Muted)
+                .iter()
+                .map(|(_principal, _relation)| {
+                    json!({
+                        "principal": _principal.to_string(),
+                        "since": _relation.since,
+                    })
+                })
+                .collect();
+
+        let profile_changes: Vec<serde_json::Value> = PROFILE_CHANGES.with(|changes| {
+            changes
+                .borrow()
+                .iter()
+                .filter(|(_, change)| change.identifier == identifier)
+                .map(|(id, change)| {
+                    json!({
+                        "id": id,
+                        "actor": change.actor.to_string(),
+                        "method": change.method,
+                        "changed_fields": change
+                            .changed_fields
+                            .iter()
+                            .map(|_field| json!({
+                                "field": _field.field,
+                                "old_value": _field.old_value,
+                                "new_value": _field.new_value,
+                            }))
+                            .collect::<Vec<serde_json::Value>>(),
+                        "created_at": change.created_at,
+                    })
+                })
+                .collect()
+        });
+
+        let starred: Vec<serde_json::Value> = profile
+            .starred
+            .iter()
+            .map(|(_identifier, _kind)| {
+                json!({
+                    "identifier": _identifier.to_string(),
+                    "kind": _kind,
+                })
+            })
+            .collect();
+
+        let username_history: Vec<serde_json::Value> = USERNAME_HISTORY.with(|history| {
+            history
+                .borrow()
+                .iter()
+                .filter(|(_, change)| change.principal == caller)
+                .map(|(username, change)| {
+                    json!({
+                        "username": username,
+                        "changed_on": change.changed_on,
+                    })
+                })
+                .collect()
+        });
+
+        let document = json!({
+            "version": DATA_EXPORT_VERSION,
+            "exported_on": time() - time() % DATA_EXPORT_WINDOW,
+            "identifier": identifier.to_string(),
+            "profile": {
+                "principal": profile.principal.to_string(),
+                "member_identifier": profile.member_identifier.to_string(),
+                "username": profile.username,
+                "display_name": profile.display_name,
+                "application_role": profile.application_role,
+                "first_name": profile.first_name,
+                "last_name": profile.last_name,
+                "privacy": profile.privacy,
+                "about": profile.about,
+                "email": profile.email,
+                "date_of_birth": profile.date_of_birth,
+                "city": profile.city,
+                "state_or_province": profile.state_or_province,
+                "country": profile.country,
+                "profile_image": profile.profile_image,
+                "banner_image": profile.banner_image,
+                "skills": profile.skills,
+                "interests": profile.interests,
+                "causes": profile.causes,
+                "website": profile.website,
+                "wallets": profile
+                    .wallets
+                    .iter()
+                    .map(|(_principal, _wallet)| json!({
+                        "principal": _principal.to_string(),
+                        "provider": _wallet.provider,
+                        "is_primary": _wallet.is_primary,
+                    }))
+                    .collect::<Vec<serde_json::Value>>(),
+                "extra": profile.extra,
+                "updated_on": profile.updated_on,
+                "created_on": profile.created_on,
+            },
+            "notification_preferences": profile.notification_preferences,
+            "username_history": username_history,
+            "profile_changes": profile_changes,
+            "relations": relations,
+            "mutes": mutes,
+            "relations_visibility": format!("{:?}", Self::get_relations_visibility(caller)),
+            "contact_groups": contact_groups,
+            "contact_notes": contact_notes,
+            "field_visibility": field_visibility,
+            "friend_requests": {
+                "incoming": incoming,
+                "outgoing": outgoing,
+            },
+            "starred": starred,
+            "documents": {
+                "code_of_conduct": profile.code_of_conduct,
+                "privacy_policy": profile.privacy_policy,
+                "terms_of_service": profile.terms_of_service,
+            },
+        });
+
+        let bytes = document.to_string().into_bytes();
+
+        // Return the whole document if it fits in a single chunk
+        if max_bytes_per_chunk == 0 || bytes.len() <= max_bytes_per_chunk {
+            return Ok((bytes, (0, 0)));
+        }
+
+        // Get the start and end index of the bytes to be returned
+        let start = chunk.saturating_mul(max_bytes_per_chunk).min(bytes.len());
+        let end = start.saturating_add(max_bytes_per_chunk).min(bytes.len());
+        let last_chunk = (bytes.len() - 1) / max_bytes_per_chunk;
+
+        Ok((bytes[start..end].to_vec(), (chunk, last_chunk)))
+    }
+
+    // Method to get the change log of a profile, only available for the owner of the profile and admins
+    pub fn get_profile_changes(
+        caller: Principal,
+        identifier: Principal,
+    ) -> Result<Vec<ProfileChangeResponse>, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller),
+            format!("identifier - {:?}", &identifier),
+        ]);
+
+        Self::_check_owner_or_admin(caller, identifier, "get_profile_changes", inputs)?;
+
+        Ok(PROFILE_CHANGES.with(|changes| {
+            changes
+                .borrow()
+                .iter()
+                .filter(|(_, change)| change.identifier == identifier)
+                .map(|(id, change)| ProfileChangeResponse {
+                    id,
+                    identifier: change.identifier,
+                    actor: change.actor,
+                    method: change.method,
+                    changed_fields: change.changed_fields,
+                    created_at: change.created_at,
+                })
+                .collect()
+        }))
+    }
+
+    // Method to reconstruct a profile as it was at a specific time by rolling back the logged changes,
+    // only available for the owner of the profile and admins
+    pub fn get_profile_at(
+        caller: Principal,
+        identifier: Principal,
+        timestamp: u64,
+    ) -> Result<ProfileResponse, ApiError> {
+        let inputs = Some(vec![
+            format!("principal - {:?}", &caller),
+            format!("identifier - {:?}", &identifier),
+            format!("timestamp - {:?}", &timestamp),
+        ]);
+
+        let profile =
+            Self::_check_owner_or_admin(caller, identifier, "get_profile_at", inputs.clone())?;
+
+        if timestamp < profile.created_on {
+            return Err(api_error(
+                ApiErrorType::NotFound,
+                "PROFILE_NOT_CREATED",
+                "The profile did not exist at this time",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "get_profile_at",
+                inputs,
+            ));
+        }
+
+        // Older changes are removed from the change log, so the profile can not be reconstructed
+        if timestamp < time().saturating_sub(PROFILE_CHANGE_RETENTION) {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "HISTORY_UNAVAILABLE",
+                "The profile history is not retained for this time",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "get_profile_at",
+                inputs,
+            ));
+        }
+
+        let reconstruction_error = |message: String| {
+            api_error(
+                ApiErrorType::Unexpected,
+                "RECONSTRUCTION_FAILED",
+                message.as_str(),
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "get_profile_at",
+                inputs.clone(),
+            )
+        };
+
+        // The change log stores the field values as JSON, so the changes are rolled back on the JSON form of the profile
+        let mut value =
+            serde_json::to_value(&profile).map_err(|err| reconstruction_error(err.to_string()))?;
+
+        // `updated_on` is not logged, it is the time of the last change at or before the timestamp
+        value["updated_on"] = PROFILE_CHANGES
+            .with(|changes| {
+                changes
+                    .borrow()
+                    .iter()
+                    .filter(|(_, change)| {
+                        change.identifier == identifier && change.created_at <= timestamp
+                    })
+                    .map(|(_, change)| change.created_at)
+                    .max()
+            })
+            .unwrap_or(profile.created_on)
+            .into();
+
+        // Roll back the changes made after the timestamp, newest first
+        let mut changes: Vec<ProfileChange> = PROFILE_CHANGES.with(|changes| {
+            changes
+                .borrow()
+                .iter()
+                .filter(|(_, change)| {
+                    change.identifier == identifier && change.created_at > timestamp
+                })
+                .map(|(_, change)| change)
+                .collect()
+        });
+        changes.reverse();
+
+        for change in changes {
+            // Changes of fields that are not on the profile, like relations, are not rolled back
+            for field in change.changed_fields {
+                if value.get(&field.field).is_none() {
+                    continue;
+                }
+                // Redacted values are rolled back to an empty value of the same type
+                let old_value = match field.old_value == REDACTED_PROFILE_FIELD_VALUE {
+                    true => match value[&field.field] {
+                        serde_json::Value::Number(_) => 0.into(),
+                        _ => String::default().into(),
+                    },
+                    false => serde_json::from_str(&field.old_value)
+                        .map_err(|err| reconstruction_error(err.to_string()))?,
+                };
+                value[field.field] = old_value;
+            }
+        }
+
+        let profile: Profile =
+            serde_json::from_value(value).map_err(|err| reconstruction_error(err.to_string()))?;
+
+        Ok(Self::_map_profile_to_profile_response(
+            identifier.to_string(),
+            profile,
+        ))
+    }
+
+    // Method to remove the profile changes that are older than the retention period, triggered by a timer
+    // the values of sensitive fields are redacted after a shorter retention period
+    pub fn prune_profile_changes() {
+        let cutoff = time().saturating_sub(PROFILE_CHANGE_RETENTION);
+        let sensitive_cutoff = time().saturating_sub(SENSITIVE_PROFILE_CHANGE_RETENTION);
+        PROFILE_CHANGES.with(|changes| {
+            let mut changes = changes.borrow_mut();
+            let ids: Vec<u64> = changes
+                .iter()
+                .take_while(|(_, change)| change.created_at < cutoff)
+                .map(|(id, _)| id)
+                .collect();
+            for id in ids {
+                changes.remove(&id);
+            }
+
+            let redact = |field: &ProfileFieldChange| {
+                SENSITIVE_PROFILE_FIELDS.contains(&field.field.as_str())
+                    && field.old_value != REDACTED_PROFILE_FIELD_VALUE
+            };
+            let sensitive: Vec<(u64, ProfileChange)> = changes
+                .iter()
+                .take_while(|(_, change)| change.created_at < sensitive_cutoff)
+                .filter(|(_, change)| change.changed_fields.iter().any(redact))
+                .collect();
+            for (id, mut change) in sensitive {
+                for field in change
+                    .changed_fields
+                    .iter_mut()
+                    .filter(|field| redact(field))
+                {
+                    field.old_value = REDACTED_PROFILE_FIELD_VALUE.to_string();
+                    field.new_value = REDACTED_PROFILE_FIELD_VALUE.to_string();
+                }
+                changes.insert(id, change);
+            }
+        });
+    }
+
+    // Method to check if a principal is allowed to use the admin methods
+    pub fn is_admin(principal: Principal) -> bool {
+        Self::get_admins().contains(&principal) || is_controller(&principal)
+    }
+
+    // Method to get the principals that can use the admin methods next to the controllers
+    pub fn get_admins() -> Vec<Principal> {
+        ADMINS.with(|admins| admins.borrow().get().admins.clone())
+    }
+
+    // Method to allow a principal to use the admin methods
+    pub fn add_admin(principal: Principal) -> Vec<Principal> {
+        let mut config = ADMINS.with(|admins| admins.borrow().get().clone());
+        if !config.admins.contains(&principal) {
+            config.admins.push(principal);
+        }
+        let _ = ADMINS.with(|admins| admins.borrow_mut().set(config));
+        Self::get_admins()
+    }
+
+    // Method to stop a principal from using the admin methods
+    pub fn remove_admin(principal: Principal) -> Vec<Principal> {
+        let mut config = ADMINS.with(|admins| admins.borrow().get().clone());
+        config.admins.retain(|admin| admin != &principal);
+        let _ = ADMINS.with(|admins| admins.borrow_mut().set(config));
+        Self::get_admins()
+    }
+
+    // Method to get a profile by identifier if the caller is the owner of the profile or an admin
+    fn _check_owner_or_admin(
+        caller: Principal,
+        identifier: Principal,
+        method_name: &str,
+        inputs: Option<Vec<String>>,
+    ) -> Result<Profile, ApiError> {
+        let profile = match ENTRIES.with(|entries| entries.borrow().get(&identifier.to_string())) {
+            None => return Err(Self::_profile_not_found_error(method_name, inputs)),
+            Some(_profile) => _profile,
+        };
+
+        if profile.principal != caller && !Self::is_admin(caller) {
+            return Err(api_error(
+                ApiErrorType::Unauthorized,
+                "UNAUTHORIZED",
+                "Only the owner of the profile or an admin can access this data",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                method_name,
+                inputs,
+            ));
+        }
+
+        Ok(profile)
+    }
+
+    // Method to update a profile in the data store and append the changed fields to the change log
+    fn _update_entry(
+        actor: Principal,
+        method: &str,
+        identifier: Principal,
+        profile: Profile,
+    ) -> Result<(Principal, Profile), ApiError> {
+        let previous = ENTRIES.with(|entries| entries.borrow().get(&identifier.to_string()));
+
+        let result = STABLE_DATA.with(|data| {
+            ENTRIES.with(|entries| Data::update_entry(data, entries, identifier, profile))
+        });
+
+        if let (Some(_previous), Ok((_, _profile))) = (previous, &result) {
+            let changed_fields = Self::_get_changed_fields(&_previous, _profile);
+            if !changed_fields.is_empty() {
+                Self::_add_profile_change(ProfileChange {
+                    identifier,
+                    actor,
+                    method: method.to_string(),
+                    changed_fields,
+                    created_at: time(),
+                });
+            }
+        }
+
+        result
+    }
+
+    // Method to append a change to the change log of the profiles
+    fn _add_profile_change(change: ProfileChange) {
+        PROFILE_CHANGES.with(|changes| {
+            let mut changes = changes.borrow_mut();
+            let id = changes.last_key_value().map(|(k, _)| k + 1).unwrap_or(0);
+            changes.insert(id, change);
+        });
+    }
+
+    // Method to get the fields that differ between two versions of a profile, `updated_on` is left out
+    fn _get_changed_fields(previous: &Profile, current: &Profile) -> Vec<ProfileFieldChange> {
+        match (
+            serde_json::to_value(previous),
+            serde_json::to_value(current),
+        ) {
+            (Ok(serde_json::Value::Object(_previous)), Ok(serde_json::Value::Object(_current))) => {
+                _current
+                    .iter()
+                    .filter(|(field, _)| field.as_str() != "updated_on")
+                    .filter(|(field, value)| _previous.get(field.as_str()) != Some(value))
+                    .map(|(field, value)| ProfileFieldChange {
+                        field: field.clone(),
+                        old_value: _previous
+                            .get(field.as_str())
+                            .map_or("null".to_string(), |v| v.to_string()),
+                        new_value: value.to_string(),
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    // Method to build the old usernames per profile from the old usernames that were stored before it existed
+    pub fn index_username_history() {
+        if !USERNAME_HISTORY_BY_PROFILE.with(|history| history.borrow().is_empty()) {
+            return;
+        }
+
+        let changes: Vec<(String, UsernameChange)> =
+            USERNAME_HISTORY.with(|history| history.borrow().iter().collect());
+        USERNAME_HISTORY_BY_PROFILE.with(|history| {
+            let mut history = history.borrow_mut();
+            for (username, change) in changes {
+                let mut _history = history
+                    .get(&change.principal.to_string())
+                    .unwrap_or_default();
+                _history.usernames.push(username);
+                _history.last_changed_on = _history.last_changed_on.max(change.changed_on);
+                history.insert(change.principal.to_string(), _history);
+            }
+        });
+    }
+
+    // Method to start the profile migration job from the first profile
+    pub fn start_profile_migration() {
+        PROFILE_MIGRATION_CURSOR.with(|cursor| *cursor.borrow_mut() = None);
+    }
+
+    // Method to mark that no profile has relations on the deprecated string map anymore,
+    // so the relations are only read from the relations store
+    pub fn mark_legacy_relations_migrated() {
+        LEGACY_RELATIONS_MIGRATED_ON.with(|migrated_on| {
+            let _ = migrated_on.borrow_mut().set(time());
+        });
+    }
+
+    // Method to re-encode a batch of stored profiles in the current storage version,
+    // profiles are migrated when decoded, so writing them back stores them in the current version
+    // returns true when all profiles have been re-encoded
+    pub fn migrate_profiles_batch(batch_size: usize) -> bool {
+        let cursor = PROFILE_MIGRATION_CURSOR.with(|cursor| cursor.borrow().clone());
+
+        let batch: Vec<(String, Profile)> = ENTRIES.with(|entries| {
+            let entries = entries.borrow();
+            match cursor {
+                None => entries.iter().take(batch_size).collect(),
+                Some(_cursor) => entries
+                    .range((Bound::Excluded(_cursor), Bound::Unbounded))
+                    .take(batch_size)
+                    .collect(),
+            }
+        });
+
+        ENTRIES.with(|entries| {
+            let mut entries = entries.borrow_mut();
+            for (identifier, profile) in batch.iter() {
+                let mut profile = profile.clone();
+                Self::_migrate_legacy_relations(&mut profile);
+                entries.insert(identifier.clone(), profile);
+            }
+        });
+
+        let is_done = batch.len() < batch_size;
+        if is_done {
+            Self::mark_legacy_relations_migrated();
+        }
+        PROFILE_MIGRATION_CURSOR.with(|cursor| {
+            *cursor.borrow_mut() = match is_done {
+                true => None,
+                false => batch.last().map(|(identifier, _)| identifier.clone()),
+            }
+        });
+
+        is_done
+    }
+
+    // Method to move the relations from the deprecated string map on the profile to the relations store,
+    // relations that already exist in the relations store are kept as is
+    fn _migrate_legacy_relations(profile: &mut Profile) {
+        let owner = profile.principal;
+        let since = profile.updated_on;
+        for (other, value) in profile.relations.drain() {
+            if let Some(kind) = RelationType::from_legacy(&value) {
+                let key = RelationKey { owner, other };
+                if !RELATIONS.with(|relations| relations.borrow().contains_key(&key)) {
+                    RELATIONS.with(|relations| {
+                        relations.borrow_mut().insert(
+                            key,
+                            Relation {
+                                kind,
+                                since,
+                                initiated_by: owner,
+                            },
+                        )
+                    });
+                }
+            }
+        }
+    }
+
+    // Method to get all relations of an owner
+    fn _get_relations(owner: Principal) -> Vec<(Principal, Relation)> {
+        let mut relations: Vec<(Principal, Relation)> = RELATIONS.with(|relations| {
+            relations
+                .borrow()
+                .range(RelationKey::first_of(owner)..)
+                .take_while(|(key, _)| key.owner == owner)
+                .map(|(key, relation)| (key.other, relation))
+                .collect()
+        });
+
+        for (other, relation) in Self::_get_legacy_relations(owner) {
+            if !relations.iter().any(|(_other, _)| _other == &other) {
+                relations.push((other, relation));
+            }
+        }
+        relations
+    }
+
+    // Method to get the relations of a profile that is not migrated yet from the deprecated string map,
+    // relations in the relations store take precedence
+    fn _get_legacy_relations(owner: Principal) -> Vec<(Principal, Relation)> {
+        if LEGACY_RELATIONS_MIGRATED_ON.with(|migrated_on| *migrated_on.borrow().get()) > 0 {
+            return vec![];
+        }
+
+        match Self::_get_profile_from_caller(owner) {
+            None => vec![],
+            Some((_, profile)) => profile
+                .relations
+                .iter()
+                .filter_map(|(other, value)| {
+                    RelationType::from_legacy(value).map(|kind| {
+                        (
+                            *other,
+                            Relation {
+                                kind,
+                                since: profile.updated_on,
+                                initiated_by: owner,
+                            },
+                        )
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    // Method to move the legacy relations of a profile to the relations store before its relations are changed,
+    // otherwise a removed relation would come back when the profile is migrated
+    fn _migrate_legacy_relations_of(owner: Principal) {
+        if Self::_get_legacy_relations(owner).is_empty() {
+            return;
+        }
+
+        if let Some((identifier, mut profile)) = Self::_get_profile_from_caller(owner) {
+            Self::_migrate_legacy_relations(&mut profile);
+            ENTRIES.with(|entries| entries.borrow_mut().insert(identifier.to_string(), profile));
+        }
+    }
+
+    // Method to get the relations of an owner by type, follows are read from their own maps
+    fn _get_relations_by_type(
+        owner: Principal,
+        relation_type: RelationType,
+    ) -> Vec<(Principal, Relation)> {
+        let owned_by = |map: &StableBTreeMap<RelationKey, Relation, Memory>| {
+            map.range(RelationKey::first_of(owner)..)
+                .take_while(|(key, _)| key.owner == owner)
+                .map(|(key, relation)| (key.other, relation))
+                .collect()
+        };
+
+        match relation_type {
+            RelationType::Follow => FOLLOWING.with(|following| owned_by(&following.borrow())),
+            RelationType::Follower => FOLLOWERS.with(|followers| owned_by(&followers.borrow())),
+            RelationType::Muted => MUTES.with(|mutes| owned_by(&mutes.borrow())),
+            _ => Self::_get_relations(owner)
+                .into_iter()
+                .filter(|(_, relation)| relation.kind == relation_type)
+                .collect(),
+        }
+    }
+
+    // Method to check if the owner has muted the other principal
+    fn _is_muted_by(owner: Principal, other: Principal) -> bool {
+        MUTES.with(|mutes| mutes.borrow().contains_key(&RelationKey { owner, other }))
+    }
+
+    // Method to check if the receiver wants the notifications of an event, principals without a profile receive all notifications.
+    // Only the profiles on this canister are checked, a receiver with a profile on another child canister
+    // also receives all notifications, looking up its preferences would need a call to that canister
+    // while the notification is written in the same call as the state change
+    fn _is_notification_enabled(
+        receiver: Principal,
+        preference: fn(&NotificationPreferences) -> &NotificationPreference,
+    ) -> bool {
+        match Self::_get_profile_from_caller(receiver) {
+            None => true,
+            Some((_, profile)) => {
+                preference(&profile.notification_preferences) == &NotificationPreference::On
+            }
+        }
+    }
+
+    // Method to check if the owner has any relation with the other principal, including follows and mutes
+    fn _has_any_relation(owner: Principal, other: Principal) -> bool {
+        let key = RelationKey { owner, other };
+        Self::_get_relation(owner, other).is_some()
+            || FOLLOWING.with(|following| following.borrow().contains_key(&key))
+            || FOLLOWERS.with(|followers| followers.borrow().contains_key(&key))
+            || Self::_is_muted_by(owner, other)
+    }
+
+    // Method to check if the owner has blocked the other principal
+    fn _is_blocked_by(owner: Principal, other: Principal) -> bool {
+        Self::_get_relation(owner, other)
+            .is_some_and(|relation| relation.kind == RelationType::Blocked)
+    }
+
+    fn _get_relation(owner: Principal, other: Principal) -> Option<Relation> {
+        RELATIONS
+            .with(|relations| relations.borrow().get(&RelationKey { owner, other }))
+            .or_else(|| {
+                Self::_get_legacy_relations(owner)
+                    .into_iter()
+                    .find(|(_other, _)| _other == &other)
+                    .map(|(_, relation)| relation)
+            })
+    }
+
+    // Method to set the relation of an owner with another principal, if existing it will be overwritten
+    fn _set_relation(
+        owner: Principal,
+        other: Principal,
+        kind: RelationType,
+        initiated_by: Principal,
+        actor: Principal,
+        method: &str,
+    ) {
+        Self::_migrate_legacy_relations_of(owner);
+        let previous = RELATIONS.with(|relations| {
+            relations.borrow_mut().insert(
+                RelationKey { owner, other },
+                Relation {
+                    kind: kind.clone(),
+                    since: time(),
+                    initiated_by,
+                },
+            )
+        });
+        Self::_log_relation_change(
+            owner,
+            other,
+            previous.map(|relation| relation.kind),
+            Some(kind),
+            actor,
+            method,
+        );
+    }
+
+    // Method to remove a relation, the other principal is also removed from the contact groups of the owner
+    fn _remove_relation(owner: Principal, other: Principal, actor: Principal, method: &str) {
+        Self::_migrate_legacy_relations_of(owner);
+        let previous = RELATIONS
+            .with(|relations| relations.borrow_mut().remove(&RelationKey { owner, other }));
+        Self::_remove_from_contact_groups(owner, other);
+        Self::_remove_orphan_contact_note(owner, other);
+        Self::_log_relation_change(
+            owner,
+            other,
+            previous.map(|relation| relation.kind),
+            None,
+            actor,
+            method,
+        );
+    }
+
+    // Relations are not stored on the profile, so a changed relation is logged in the change log of the owner
+    // as the field `relations.<principal>` and updates the `updated_on` of the profile
+    fn _log_relation_change(
+        owner: Principal,
+        other: Principal,
+        previous: Option<RelationType>,
+        current: Option<RelationType>,
+        actor: Principal,
+        method: &str,
+    ) {
+        if previous == current {
+            return;
+        }
+
+        let (identifier, mut profile) = match Self::_get_profile_from_caller(owner) {
+            None => return,
+            Some(_profile) => _profile,
+        };
+        profile.updated_on = time();
+        ENTRIES.with(|entries| entries.borrow_mut().insert(identifier.to_string(), profile));
+
+        let to_value = |kind: Option<RelationType>| match kind {
+            None => "null".to_string(),
+            Some(_kind) => json!(_kind.to_string()).to_string(),
+        };
+        Self::_add_profile_change(ProfileChange {
+            identifier,
+            actor,
+            method: method.to_string(),
+            changed_fields: vec![ProfileFieldChange {
+                field: format!("relations.{}", other),
+                old_value: to_value(previous),
+                new_value: to_value(current),
+            }],
+            created_at: time(),
+        });
+    }
+
+    // Method to remove a follow from both the following and the followers map, returns if the follow existed
+    fn _remove_follow(follower: Principal, followed: Principal) -> bool {
+        FOLLOWERS.with(|followers| {
+            followers.borrow_mut().remove(&RelationKey {
+                owner: followed,
+                other: follower,
+            })
+        });
+        let existed = FOLLOWING
+            .with(|following| {
+                following.borrow_mut().remove(&RelationKey {
+                    owner: follower,
+                    other: followed,
+                })
+            })
+            .is_some();
+        Self::_remove_orphan_contact_note(follower, followed);
+        Self::_remove_orphan_contact_note(followed, follower);
+        existed
+    }
+
+    // Method to remove the note of the owner about the other principal once they have no relation left,
+    // notes can only be added to relations
+    fn _remove_orphan_contact_note(owner: Principal, other: Principal) {
+        if Self::_has_any_relation(owner, other) {
+            return;
+        }
+        CONTACT_NOTES.with(|notes| notes.borrow_mut().remove(&RelationKey { owner, other }));
+    }
+
+    // Method to get where the notifications are send to
+    pub fn get_notification_environment() -> NotificationEnvironment {
+        NOTIFICATION_ENVIRONMENT.with(|environment| environment.borrow().get().clone())
+    }
+
+    // Method to change where the notifications are send to
+    pub fn set_notification_environment(
+        environment: NotificationEnvironment,
+    ) -> NotificationEnvironment {
+        let _ = NOTIFICATION_ENVIRONMENT
+            .with(|_environment| _environment.borrow_mut().set(environment.clone()));
+        environment
+    }
+
+    // Method to get the notifications that are recorded instead of send, newest first
+    pub fn get_local_notifications(limit: usize, page: usize) -> PagedResponse<LocalNotification> {
+        let mut notifications: Vec<LocalNotification> = LOCAL_NOTIFICATIONS
+            .with(|notifications| notifications.borrow().iter().map(|(_, n)| n).collect());
+        notifications.reverse();
+        get_paged_data(notifications, limit, page)
+    }
+
+    fn get_environment() -> Result<Environment, ApiError> {
+        let not_found_error = |message: &str| {
+            Err(api_error(
+                ApiErrorType::NotFound,
+                "ENVIRONMENT_NOT_FOUND",
+                message,
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "get_environment",
+                None,
+            ))
+        };
+
+        match Self::get_notification_environment() {
+            NotificationEnvironment::Production => Ok(Environment::Production),
+            NotificationEnvironment::Staging => Ok(Environment::Staging),
+            NotificationEnvironment::Development => Ok(Environment::Development),
+            NotificationEnvironment::Disabled => not_found_error("Notifications are disabled"),
+            NotificationEnvironment::Auto => match id().to_string().as_str() {
+                "4vy4w-gaaaa-aaaap-aa4pa-cai" => Ok(Environment::Production),
+                "5ycyv-iiaaa-aaaap-abgia-cai" => Ok(Environment::Staging),
+                "crorp-uaaaa-aaaap-abqwq-cai" => Ok(Environment::Development),
+                _ => not_found_error("No notification environment found for this canister"),
+            },
+        }
+    }
+
+    // Method to deliver the notifications in the outbox that are due,
+    // failed deliveries are retried with exponential backoff and moved to the dead letters after the last attempt
+    pub async fn process_notification_outbox() {
+        let environment = match Self::get_environment() {
+            Ok(environment) => environment,
+            Err(_) => {
+                // Notifications are disabled, so the outbox is recorded locally
+                for (id, notification) in Self::_take_due_outbox_notifications(u64::MAX, usize::MAX)
+                {
+                    if let Ok(data) =
+                        serde_json::from_str::<SendNotificationData>(&notification.data)
+                    {
+                        match notification.sender {
+                            Some(sender) => LocalNotificationSink.add_notification(
+                                sender,
+                                data,
+                                notification.metadata,
+                            ),
+                            None => LocalNotificationSink
+                                .silent_notification(data, notification.metadata),
+                        }
+                    }
+                    NOTIFICATION_OUTBOX.with(|outbox| outbox.borrow_mut().remove(&id));
+                }
+                return;
+            }
+        };
+
+        let due = Self::_take_due_outbox_notifications(time(), NOTIFICATION_OUTBOX_BATCH_SIZE);
+        for (id, notification) in due {
+            let result = OutboxNotificationSink::deliver(environment.clone(), notification).await;
+            Self::_complete_outbox_notification(id, result);
+        }
+    }
+
+    // Method to get the notifications in the outbox that failed at least once, oldest first
+    pub fn get_stuck_notifications(
+        limit: usize,
+        page: usize,
+    ) -> PagedResponse<OutboxNotificationResponse> {
+        let notifications = NOTIFICATION_OUTBOX.with(|outbox| {
+            outbox
+                .borrow()
+                .iter()
+                .filter(|(_, notification)| notification.attempts > 0)
+                .map(|(id, notification)| OutboxNotificationResponse { id, notification })
+                .collect()
+        });
+        get_paged_data(notifications, limit, page)
+    }
+
+    // Method to get the notifications of which all delivery attempts failed, oldest first
+    pub fn get_notification_dead_letters(
+        limit: usize,
+        page: usize,
+    ) -> PagedResponse<OutboxNotificationResponse> {
+        let notifications = NOTIFICATION_DEAD_LETTERS.with(|dead_letters| {
+            dead_letters
+                .borrow()
+                .iter()
+                .map(|(id, notification)| OutboxNotificationResponse { id, notification })
+                .collect()
+        });
+        get_paged_data(notifications, limit, page)
+    }
+
+    // Method to move a dead letter back to the outbox, so it is delivered by the next run of the outbox job
+    pub fn retry_dead_letter_notification(id: u64) -> Result<bool, ApiError> {
+        // Dead letters from before the id counter can share their id with a notification in the outbox
+        if NOTIFICATION_OUTBOX.with(|outbox| outbox.borrow().contains_key(&id)) {
+            return Err(api_error(
+                ApiErrorType::BadRequest,
+                "ID_IN_OUTBOX",
+                "A notification with this id is already in the outbox",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "retry_dead_letter_notification",
+                Some(vec![format!("id - {:?}", &id)]),
+            ));
+        }
+
+        match NOTIFICATION_DEAD_LETTERS.with(|dead_letters| dead_letters.borrow_mut().remove(&id)) {
+            None => Err(api_error(
+                ApiErrorType::NotFound,
+                "DEAD_LETTER_NOT_FOUND",
+                "Dead letter not found",
+                STABLE_DATA
+                    .with(|data| Data::get_name(data.borrow().get()))
+                    .as_str(),
+                "retry_dead_letter_notification",
+                Some(vec![format!("id - {:?}", &id)]),
+            )),
+            Some(mut notification) => {
+                notification.attempts = 0;
+                notification.next_attempt_at = time();
+                NOTIFICATION_OUTBOX.with(|outbox| outbox.borrow_mut().insert(id, notification));
+                Ok(true)
+            }
+        }
+    }
+
+    // Time to wait before the next attempt, after the given number of failed attempts
+    fn _get_notification_retry_delay(attempts: u32) -> u64 {
+        NOTIFICATION_RETRY_BASE_DELAY
+            .saturating_mul(2u64.saturating_pow(attempts.saturating_sub(1)))
+    }
+
+    // Method to get the notifications that are due, the next attempt is pushed back
+    // so the notifications are not picked up again by another run while they are being delivered
+    fn _take_due_outbox_notifications(now: u64, limit: usize) -> Vec<(u64, OutboxNotification)> {
+        NOTIFICATION_OUTBOX.with(|outbox| {
+            let mut outbox = outbox.borrow_mut();
+            let due: Vec<(u64, OutboxNotification)> = outbox
+                .iter()
+                .filter(|(_, notification)| notification.next_attempt_at <= now)
+                .take(limit)
+                .collect();
+
+            for (id, notification) in due.iter() {
+                let mut _notification = notification.clone();
+                _notification.next_attempt_at =
+                    time() + Self::_get_notification_retry_delay(notification.attempts + 1);
+                outbox.insert(*id, _notification);
+            }
+            due
+        })
+    }
+
+    fn _complete_outbox_notification(id: u64, result: Result<(), String>) {
+        NOTIFICATION_OUTBOX.with(|outbox| {
+            let mut outbox = outbox.borrow_mut();
+            let mut notification = match outbox.remove(&id) {
+                None => return,
+                Some(notification) => notification,
+            };
+            let error = match result {
+                Ok(_) => return,
+                Err(error) => error,
+            };
+
+            notification.attempts += 1;
+            notification.last_error = Some(error);
+            if notification.attempts >= NOTIFICATION_MAX_ATTEMPTS {
+                NOTIFICATION_DEAD_LETTERS
+                    .with(|dead_letters| dead_letters.borrow_mut().insert(id, notification));
+            } else {
+                notification.next_attempt_at =
+                    time() + Self::_get_notification_retry_delay(notification.attempts);
+                outbox.insert(id, notification);
+            }
+        });
+    }
+
+    // Method to replace where the notifications are send to, `None` restores the sink of the notification environment
+    pub fn set_notification_sink(sink: Option<Rc<dyn NotificationSink>>) {
+        NOTIFICATION_SINK.with(|_sink| *_sink.borrow_mut() = sink);
+    }
+
+    // Notifications are written to the outbox in the same call as the state change,
+    // when there is no notification environment the notification is recorded locally instead
+    fn _notification_sink() -> Rc<dyn NotificationSink> {
+        if let Some(sink) = NOTIFICATION_SINK.with(|sink| sink.borrow().clone()) {
+            return sink;
+        }
+
+        match Self::get_environment() {
+            Ok(_) => Rc::new(OutboxNotificationSink),
+            Err(_) => Rc::new(LocalNotificationSink),
+        }
+    }
+
+    // Method to send a notification on behalf of the sender
+    fn _notify(sender: Principal, data: SendNotificationData, metadata: String) {
+        Self::_notification_sink().add_notification(sender, data, metadata);
+    }
+
+    // Method to send a notification that is not stored by the notification canister
+    fn _notify_silent(data: SendNotificationData, metadata: String) {
+        Self::_notification_sink().silent_notification(data, metadata);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notification_sink::{
+        InMemoryNotificationSink, ADD_NOTIFICATION_METHOD, SILENT_NOTIFICATION_METHOD,
+    };
+    use ic_scalable_canister::ic_scalable_misc::helpers::serialize_helper::deserialize;
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+
+    fn principal(n: u8) -> Principal {
+        Principal::from_slice(&[n; 29])
+    }
+
+    fn tag(err: ApiError) -> String {
+        match err {
+            ApiError::Unexpected(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::NotFound(message)
+            | ApiError::BadRequest(message)
+            | ApiError::SerializeError(message)
+            | ApiError::DeserializeError(message)
+            | ApiError::CanisterAtCapacity(message) => message.tag,
+            ApiError::ValidationError(_) => "VALIDATION_ERROR".to_string(),
+            ApiError::UpdateRequired(_) => "UPDATE_REQUIRED".to_string(),
+        }
+    }
+
+    // Profiles are inserted directly, `add_profile` needs the replica to create an identifier
+    fn insert_profile(owner: Principal) {
+        let profile = Profile {
+            principal: owner,
+            member_identifier: Principal::anonymous(),
+            username: format!("user{}", owner.as_slice()[0]),
+            display_name: "Test profile".to_string(),
+            application_role: ApplicationRole::default(),
+            first_name: String::default(),
+            last_name: String::default(),
+            privacy: ProfilePrivacy::Public,
+            about: String::default(),
+            email: String::default(),
+            date_of_birth: 0,
+            city: String::default(),
+            state_or_province: String::default(),
+            country: String::default(),
+            profile_image: Asset::None,
+            banner_image: Asset::None,
+            skills: vec![],
+            interests: vec![],
+            causes: vec![],
+            website: String::default(),
+            code_of_conduct: DocumentDetails::default(),
+            privacy_policy: None,
+            terms_of_service: None,
+            wallets: HashMap::new(),
+            starred: HashMap::new(),
+            relations: HashMap::new(),
+            extra: String::default(),
+            notification_preferences: NotificationPreferences::default(),
+            updated_on: time(),
+            created_on: time(),
+        };
+        ENTRIES.with(|entries| entries.borrow_mut().insert(owner.to_string(), profile));
+    }
+
+    fn make_friends(requester: Principal, receiver: Principal) {
+        let request = Store::add_friend_request(requester, receiver, "hi".to_string()).unwrap();
+        Store::accept_friend_request(receiver, request.id).unwrap();
+    }
+
+    // Runs a future that completes without calling another canister
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("The future waits for another canister"),
+        }
+    }
+
+    #[test]
+    fn endpoints_return_errors_for_missing_profiles() {
+        let caller = principal(1);
+        let other = principal(2);
+
+        let profile_not_found = vec![
+            Store::update_profile(caller, UpdateProfile::default()).map(|_| ()),
+            Store::change_username(caller, "username".to_string()).map(|_| ()),
+            Store::add_wallet(
+                caller,
+                PostWallet {
+                    provider: "plug".to_string(),
+                    principal: other,
+                },
+            )
+            .map(|_| ()),
+            Store::set_wallet_as_primary(caller, other).map(|_| ()),
+            Store::remove_wallet(caller, other).map(|_| ()),
+            Store::remove_starred(caller, other).map(|_| ()),
+            Store::add_friend_request(caller, other, "hi".to_string()).map(|_| ()),
+            Store::block_user(caller, other).map(|_| ()),
+            Store::unblock_user(caller, other).map(|_| ()),
+            Store::follow(caller, other).map(|_| ()),
+            Store::mute(caller, other).map(|_| ()),
+            Store::add_contact_group(
+                caller,
+                PostContactGroup {
+                    name: "close friends".to_string(),
+                    members: vec![],
+                },
+            )
+            .map(|_| ()),
+            Store::set_contact_note(
+                caller,
+                other,
+                PostContactNote {
+                    note: "met at ICP conf".to_string(),
+                    tags: vec![],
+                },
+            )
+            .map(|_| ()),
+            Store::approve_code_of_conduct(caller, 1).map(|_| ()),
+            Store::approve_privacy_policy(caller, 1).map(|_| ()),
+            Store::approve_terms_of_service(caller, 1).map(|_| ()),
+            Store::delete_profile(caller, true).map(|_| ()),
+            Store::export_my_data(caller, 0, 1_000_000).map(|_| ()),
+            Store::get_profile_by_user_principal(caller, other).map(|_| ()),
+            Store::recommend_profiles(caller, 10).map(|_| ()),
+            Store::set_relations_visibility(caller, RelationsVisibility::OnlyMe).map(|_| ()),
+            Store::get_relations_count(caller, other, RelationType::Friend).map(|_| ()),
+        ];
+        for result in profile_not_found {
+            assert_eq!(tag(result.unwrap_err()), "PROFILE_NOT_FOUND");
+        }
+
+        assert_eq!(
+            tag(Store::accept_friend_request(caller, 0).unwrap_err()),
+            "REQUEST_NOT_FOUND"
+        );
+        assert_eq!(
+            tag(Store::decline_friend_request(caller, 0).unwrap_err()),
+            "REQUEST_NOT_FOUND"
+        );
+        assert_eq!(
+            tag(Store::remove_friend_request(caller, 0).unwrap_err()),
+            "REQUEST_NOT_FOUND"
+        );
+        assert_eq!(
+            tag(Store::remove_friend(caller, other).unwrap_err()),
+            "FRIEND_NOT_FOUND"
+        );
+        assert!(Store::add_starred(caller, other).is_err());
+        assert!(Store::get_profile_by_identifier(caller, other).is_err());
+        assert!(Store::resolve_username(caller, "unknown".to_string()).is_err());
+        assert!(Store::unfollow(caller, other).is_err());
+        assert!(Store::unmute(caller, other).is_err());
+        assert!(Store::edit_contact_group(
+            caller,
+            0,
+            PostContactGroup {
+                name: "close friends".to_string(),
+                members: vec![],
+            }
+        )
+        .is_err());
+        assert!(Store::delete_contact_group(caller, 0).is_err());
+        assert!(Store::get_contact_note(caller, other).is_err());
+        assert!(Store::clear_contact_note(caller, other).is_err());
+        assert!(Store::cancel_profile_deletion(caller).is_err());
+        assert!(Store::get_profile_changes(caller, other).is_err());
+        assert!(Store::get_profile_at(caller, other, time()).is_err());
+
+        // Queries without a profile return empty results
+        assert!(Store::get_relations(caller, RelationType::Friend).is_empty());
+        assert!(Store::get_mutual_friends(caller, other).is_empty());
+        assert!(Store::get_friend_suggestions(caller, 10).is_empty());
+        assert!(Store::get_profiles_by_user_principal(caller, vec![other]).is_empty());
+        assert!(Store::get_profiles_by_identifier(caller, vec![other]).is_empty());
+        assert!(Store::get_friend_requests(caller).is_empty());
+        assert!(Store::get_contact_groups(caller).is_empty());
+        assert!(Store::get_contact_notes(caller).is_empty());
+        assert!(Store::get_profile_deletion(caller).is_none());
+    }
+
+    #[test]
+    fn friend_request_flow_without_notification_environment() {
+        let requester = principal(1);
+        let receiver = principal(2);
+        insert_profile(requester);
+        insert_profile(receiver);
+
+        assert_eq!(
+            tag(Store::get_environment().unwrap_err()),
+            "ENVIRONMENT_NOT_FOUND"
+        );
+        assert_eq!(
+            Store::get_notification_environment(),
+            NotificationEnvironment::Auto
+        );
+
+        let request = Store::add_friend_request(requester, receiver, "hi".to_string()).unwrap();
+        assert!(Store::accept_friend_request(receiver, request.id).unwrap());
+        assert_eq!(
+            Store::get_relations(requester, RelationType::Friend),
+            vec![receiver]
+        );
+        assert!(Store::remove_friend(requester, receiver).unwrap());
+        assert!(Store::get_relations(receiver, RelationType::Friend).is_empty());
+
+        let request = Store::add_friend_request(requester, receiver, "hi".to_string()).unwrap();
+        assert!(Store::decline_friend_request(receiver, request.id).unwrap());
+
+        assert!(Store::follow(requester, receiver).unwrap());
+        Store::expire_friend_requests();
+
+        // Request, accept, remove, request, decline and follow
+        let notifications = Store::get_local_notifications(10, 1);
+        assert_eq!(notifications.total, 6);
+        assert_eq!(notifications.data[0].receivers, vec![receiver]);
+        assert!(notifications.data[0].notification_type.contains("follow"));
+    }
+
+    #[test]
+    fn friend_request_flow_emits_notifications() {
+        let requester = principal(1);
+        let receiver = principal(2);
+        insert_profile(requester);
+        insert_profile(receiver);
+
+        let sink = Rc::new(InMemoryNotificationSink::default());
+        Store::set_notification_sink(Some(sink.clone()));
+        let friend_request =
+            |notification: &(Option<Principal>, SendNotificationData)| match &notification.1.data {
+                NotificationType::FriendRequest(data) => {
+                    Some((notification.0, data.from, data.to, data.accepted))
+                }
+                _ => None,
+            };
+
+        let request = Store::add_friend_request(requester, receiver, "hi".to_string()).unwrap();
+        let notification = sink.notifications.borrow_mut().pop().unwrap();
+        assert_eq!(notification.1.receivers, vec![receiver]);
+        assert_eq!(
+            friend_request(&notification),
+            Some((Some(requester), requester, receiver, None))
+        );
+
+        Store::accept_friend_request(receiver, request.id).unwrap();
+        let notification = sink.notifications.borrow_mut().pop().unwrap();
+        assert_eq!(notification.1.receivers, vec![requester]);
+        assert_eq!(
+            friend_request(&notification),
+            Some((Some(requester), requester, receiver, Some(true)))
+        );
+
+        Store::remove_friend(requester, receiver).unwrap();
+        let notification = sink.notifications.borrow_mut().pop().unwrap();
+        assert_eq!(notification.0, None);
+        assert_eq!(notification.1.receivers, vec![receiver]);
+        assert!(matches!(
+            notification.1.data,
+            NotificationType::FriendRemove(removed) if removed == receiver
+        ));
+
+        let request = Store::add_friend_request(requester, receiver, "hi".to_string()).unwrap();
+        Store::decline_friend_request(receiver, request.id).unwrap();
+        let notification = sink.notifications.borrow_mut().pop().unwrap();
+        assert_eq!(notification.1.receivers, vec![requester]);
+        assert_eq!(
+            friend_request(&notification),
+            Some((Some(requester), requester, receiver, Some(false)))
+        );
+
+        // Failed calls and muted senders do not emit notifications
+        sink.notifications.borrow_mut().clear();
+        assert!(Store::accept_friend_request(receiver, request.id).is_err());
+        assert!(Store::remove_friend(requester, receiver).is_err());
+        Store::mute(receiver, requester).unwrap();
+        Store::add_friend_request(requester, receiver, "hi".to_string()).unwrap();
+        assert!(sink.notifications.borrow().is_empty());
+    }
+
+    #[test]
+    fn notifications_follow_the_preferences_of_the_receiver() {
+        let requester = principal(1);
+        let receiver = principal(2);
+        insert_profile(requester);
+        insert_profile(receiver);
+
+        let sink = Rc::new(InMemoryNotificationSink::default());
+        Store::set_notification_sink(Some(sink.clone()));
+
+        assert_eq!(
+            Store::get_notification_preferences(receiver).unwrap(),
+            NotificationPreferences::default()
+        );
+        let preferences = NotificationPreferences {
+            friend_request: NotificationPreference::Off,
+            ..Default::default()
+        };
+        assert_eq!(
+            Store::set_notification_preferences(receiver, preferences.clone()).unwrap(),
+            preferences
+        );
+        assert_eq!(
+            Store::get_notification_preferences(receiver).unwrap(),
+            preferences
+        );
+
+        // The receiver turned friend requests off, the requester still gets the acceptance
+        let request = Store::add_friend_request(requester, receiver, "hi".to_string()).unwrap();
+        assert!(sink.notifications.borrow().is_empty());
+        Store::accept_friend_request(receiver, request.id).unwrap();
+        assert_eq!(
+            sink.notifications.borrow_mut().pop().unwrap().1.receivers,
+            vec![requester]
+        );
+
+        Store::set_notification_preferences(
+            receiver,
+            NotificationPreferences {
+                friend_removed: NotificationPreference::Off,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        Store::remove_friend(requester, receiver).unwrap();
+        assert!(sink.notifications.borrow().is_empty());
+
+        assert_eq!(
+            tag(Store::set_notification_preferences(principal(3), preferences).unwrap_err()),
+            "PROFILE_NOT_FOUND"
+        );
+    }
+
+    #[test]
+    fn old_usernames_are_reserved_and_changes_have_a_cooldown() {
+        let owner = principal(1);
+        let other = principal(2);
+        insert_profile(owner);
+        insert_profile(other);
+
+        Store::change_username(owner, "renamed".to_string()).unwrap();
+        assert_eq!(
+            tag(Store::change_username(owner, "renamed_again".to_string()).unwrap_err()),
+            "USERNAME_CHANGE_COOLDOWN"
+        );
+        assert!(validate_username("ab".to_string()).is_err());
+
+        // The old username is reserved for the owner and still resolves to its profile
+        assert_eq!(
+            tag(Store::change_username(other, "user1".to_string()).unwrap_err()),
+            "USERNAME_TAKEN"
+        );
+        assert_eq!(
+            Store::resolve_username(other, "user1".to_string())
+                .unwrap()
+                .principal,
+            owner
+        );
+
+        // After the reservation period the old username is released
+        USERNAME_HISTORY.with(|history| {
+            let mut history = history.borrow_mut();
+            let mut change = history.get(&"user1".to_string()).unwrap();
+            change.changed_on = time() - USERNAME_RESERVATION_PERIOD;
+            history.insert("user1".to_string(), change);
+        });
+        assert_eq!(
+            tag(Store::resolve_username(other, "user1".to_string()).unwrap_err()),
+            "PROFILE_NOT_FOUND"
+        );
+        Store::change_username(other, "user1".to_string()).unwrap();
+
+        // After the cooldown the owner can change the username again
+        USERNAME_HISTORY_BY_PROFILE.with(|history| {
+            let mut history = history.borrow_mut();
+            let mut _history = history.get(&owner.to_string()).unwrap();
+            assert_eq!(_history.usernames, vec!["user1".to_string()]);
+            _history.last_changed_on = time() - USERNAME_CHANGE_COOLDOWN;
+            history.insert(owner.to_string(), _history);
+        });
+        Store::change_username(owner, "renamed_again".to_string()).unwrap();
+    }
+
+    #[test]
+    fn scheduled_deletions_can_be_cancelled_and_erase_the_profile() {
+        let owner = principal(1);
+        let friend = principal(2);
+        insert_profile(owner);
+        insert_profile(friend);
+        let request = Store::add_friend_request(owner, friend, "hi".to_string()).unwrap();
+        Store::accept_friend_request(friend, request.id).unwrap();
+
+        // Data held about the profile outside of its own entry
+        Store::set_contact_note(
+            friend,
+            owner,
+            PostContactNote {
+                note: "met at the meetup".to_string(),
+                tags: vec![],
+            },
+        )
+        .unwrap();
+        Store::add_contact_group(
+            friend,
+            PostContactGroup {
+                name: "close".to_string(),
+                members: vec![owner],
+            },
+        )
+        .unwrap();
+        CLEAR_RELATIONS_NONCES.with(|nonces| {
+            nonces
+                .borrow_mut()
+                .insert(owner, ("nonce".to_string(), time()))
+        });
+        let notification = OutboxNotification {
+            sender: Some(owner),
+            data: format!("{{\"receivers\":[\"{}\"]}}", friend),
+            metadata: "user1 says hi".to_string(),
+            attempts: 0,
+            next_attempt_at: time(),
+            last_error: None,
+            created_at: time(),
+        };
+        NOTIFICATION_OUTBOX.with(|outbox| outbox.borrow_mut().insert(0, notification.clone()));
+        NOTIFICATION_DEAD_LETTERS.with(|dead_letters| {
+            dead_letters.borrow_mut().insert(
+                1,
+                OutboxNotification {
+                    sender: None,
+                    data: format!("{{\"receivers\":[\"{}\"]}}", owner),
+                    ..notification
+                },
+            )
+        });
+        LOCAL_NOTIFICATIONS.with(|notifications| {
+            notifications.borrow_mut().insert(
+                0,
+                LocalNotification {
+                    sender: None,
+                    receivers: vec![owner],
+                    notification_type: "\"Silent\"".to_string(),
+                    metadata: "user1 says hi".to_string(),
+                    created_at: time(),
+                },
+            )
+        });
+        assert!(FRIEND_REQUESTS_SENT.with(|sent| sent.borrow().contains_key(&owner.to_string())));
+
+        let deletion = Store::delete_profile(owner, false).unwrap();
+        assert_eq!(
+            deletion.delete_after,
+            time() + PROFILE_DELETION_GRACE_PERIOD
+        );
+        assert_eq!(deletion.deleted_on, None);
+
+        // During the grace period the profile stays readable and the deletion can be cancelled
+        Store::process_profile_deletions();
+        assert!(Store::get_profile_by_user_principal(friend, owner).is_ok());
+        assert!(Store::cancel_profile_deletion(owner).unwrap());
+        assert_eq!(
+            tag(Store::cancel_profile_deletion(owner).unwrap_err()),
+            "DELETION_NOT_FOUND"
+        );
+
+        Store::delete_profile(owner, false).unwrap();
+        PROFILE_DELETIONS.with(|deletions| {
+            let mut deletions = deletions.borrow_mut();
+            let mut deletion = deletions.get(&owner.to_string()).unwrap();
+            deletion.delete_after = time();
+            deletions.insert(owner.to_string(), deletion);
+        });
+        Store::process_profile_deletions();
+
+        assert!(Store::get_profile_deletion(owner).is_none());
+        assert_eq!(
+            tag(Store::get_profile_by_user_principal(friend, owner).unwrap_err()),
+            "PROFILE_DELETED"
+        );
+        assert_eq!(
+            tag(Store::get_profile_by_identifier(friend, owner).unwrap_err()),
+            "PROFILE_DELETED"
+        );
+        assert!(Store::get_profiles_by_user_principal(friend, vec![owner]).is_empty());
+        assert!(Store::get_profiles_by_identifier(friend, vec![owner]).is_empty());
+        assert!(Store::resolve_username(friend, "user1".to_string()).is_err());
+        assert!(Store::get_relations(friend, RelationType::Friend).is_empty());
+        assert!(Store::get_contact_notes(friend).is_empty());
+        assert!(Store::get_contact_groups(friend)
+            .iter()
+            .all(|group| group.members.is_empty()));
+        assert!(CLEAR_RELATIONS_NONCES.with(|nonces| nonces.borrow().is_empty()));
+        assert!(FRIEND_REQUESTS_SENT.with(|sent| sent.borrow().is_empty()));
+        assert!(NOTIFICATION_OUTBOX.with(|outbox| outbox.borrow().is_empty()));
+        assert!(NOTIFICATION_DEAD_LETTERS.with(|dead_letters| dead_letters.borrow().is_empty()));
+        assert!(LOCAL_NOTIFICATIONS.with(|notifications| notifications.borrow().is_empty()));
+    }
+
+    #[test]
+    fn immediate_deletion_keeps_the_scheduled_request() {
+        let owner = principal(1);
+        insert_profile(owner);
+
+        let scheduled = Store::delete_profile(owner, false).unwrap();
+        let deletion = Store::delete_profile(owner, true).unwrap();
+        assert_eq!(deletion.requested_on, scheduled.requested_on);
+        assert_eq!(deletion.deleted_on, Some(time()));
+        assert!(Store::get_profile_deletion(owner).is_none());
+        assert_eq!(
+            tag(Store::delete_profile(owner, true).unwrap_err()),
+            "PROFILE_NOT_FOUND"
+        );
+    }
+
+    #[test]
+    fn data_export_chunks_combine_to_the_document() {
+        let owner = principal(1);
+        insert_profile(owner);
+
+        let (document, _) = Store::export_my_data(owner, 0, 0).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&document).unwrap();
+        assert_eq!(json["version"], DATA_EXPORT_VERSION);
+        for key in [
+            "notification_preferences",
+            "profile_changes",
+            "field_visibility",
+            "relations_visibility",
+            "contact_groups",
+            "mutes",
+        ] {
+            assert!(!json[key].is_null(), "{} is missing", key);
+        }
+
+        let mut combined = vec![];
+        let mut chunk = 0;
+        loop {
+            let (bytes, (_, last_chunk)) = Store::export_my_data(owner, chunk, 100).unwrap();
+            combined.extend(bytes);
+            if chunk == last_chunk {
+                break;
+            }
+            chunk += 1;
+        }
+        assert_eq!(combined, document);
+
+        // Chunks past the end are empty instead of overflowing
+        let (bytes, _) = Store::export_my_data(owner, usize::MAX, 2).unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn admins_are_stored_in_stable_config() {
+        let admin = principal(9);
+        assert!(!Store::is_admin(admin));
+        assert_eq!(Store::get_admins().len(), 1);
+
+        Store::add_admin(admin);
+        assert!(Store::is_admin(admin));
+        assert_eq!(Store::add_admin(admin).len(), 2);
+
+        Store::remove_admin(admin);
+        assert!(!Store::is_admin(admin));
+    }
+
+    #[test]
+    fn profile_history_is_reconstructed_and_sensitive_values_are_redacted() {
+        let owner = principal(1);
+        insert_profile(owner);
+        ENTRIES.with(|entries| {
+            let mut entries = entries.borrow_mut();
+            let mut profile = entries.get(&owner.to_string()).unwrap();
+            profile.created_on = time() - PROFILE_CHANGE_RETENTION / 2;
+            entries.insert(owner.to_string(), profile);
+        });
+        let set_email = |email: &str, created_at: u64| {
+            let (_, mut profile) = Store::_get_profile_from_caller(owner).unwrap();
+            profile.email = email.to_string();
+            Store::_update_entry(owner, "update_profile", owner, profile).unwrap();
+            PROFILE_CHANGES.with(|changes| {
+                let mut changes = changes.borrow_mut();
+                let (id, mut change) = changes.last_key_value().unwrap();
+                change.created_at = created_at;
+                changes.insert(id, change);
+            });
+        };
+        let first_change = time() - SENSITIVE_PROFILE_CHANGE_RETENTION * 2;
+        set_email("first@example.com", first_change);
+        set_email("second@example.com", time() - 100);
+
+        let profile = Store::get_profile_at(owner, owner, time() - 200).unwrap();
+        assert_eq!(profile.email, "first@example.com");
+        assert_eq!(profile.updated_on, first_change);
+        let profile = Store::get_profile_at(owner, owner, first_change - 1).unwrap();
+        assert_eq!(profile.email, "");
+
+        // The change older than the sensitive retention is redacted, the recent one is kept
+        Store::prune_profile_changes();
+        let changes = Store::get_profile_changes(owner, owner).unwrap();
+        assert_eq!(
+            changes[0].changed_fields[0].new_value,
+            REDACTED_PROFILE_FIELD_VALUE
+        );
+        assert_eq!(
+            changes[1].changed_fields[0].old_value,
+            "\"first@example.com\""
+        );
+        let profile = Store::get_profile_at(owner, owner, first_change - 1).unwrap();
+        assert_eq!(profile.email, "");
+    }
+
+    #[test]
+    fn legacy_relations_are_read_until_migrated_and_changes_are_logged() {
+        let owner = principal(1);
+        let friend = principal(2);
+        let blocked = principal(3);
+        insert_profile(owner);
+        insert_profile(friend);
+        ENTRIES.with(|entries| {
+            let mut entries = entries.borrow_mut();
+            let mut profile = entries.get(&owner.to_string()).unwrap();
+            profile.relations.insert(friend, "friend".to_string());
+            profile.relations.insert(blocked, "blocked".to_string());
+            profile.created_on = time() - 10;
+            entries.insert(owner.to_string(), profile);
+        });
+        Store::start_profile_migration();
+
+        // Before the migration job reached the profile, the legacy relations are used
+        assert_eq!(
+            Store::get_relations(owner, RelationType::Friend),
+            vec![friend]
+        );
+        assert!(Store::_is_blocked_by(owner, blocked));
+
+        // Changing a relation migrates the profile first, so removed relations do not come back
+        Store::remove_friend(owner, friend).unwrap();
+        assert!(Store::get_relations(owner, RelationType::Friend).is_empty());
+        assert!(Store::_is_blocked_by(owner, blocked));
+        while !Store::migrate_profiles_batch(1) {}
+        assert!(Store::get_relations(owner, RelationType::Friend).is_empty());
+        assert!(Store::_is_blocked_by(owner, blocked));
+
+        // Once migrated, later upgrades do not read the profiles for legacy relations again
+        Store::start_profile_migration();
+        assert!(LEGACY_RELATIONS_MIGRATED_ON.with(|migrated_on| *migrated_on.borrow().get()) > 0);
+        ENTRIES.with(|entries| {
+            let mut entries = entries.borrow_mut();
+            let mut profile = entries.get(&owner.to_string()).unwrap();
+            profile.relations.insert(friend, "friend".to_string());
+            entries.insert(owner.to_string(), profile);
+        });
+        assert!(Store::get_relations(owner, RelationType::Friend).is_empty());
+
+        let changes = Store::get_profile_changes(owner, owner).unwrap();
+        let change = changes.last().unwrap();
+        assert_eq!(change.method, "remove_friend");
+        assert_eq!(
+            change.changed_fields[0].field,
+            format!("relations.{}", friend)
+        );
+        assert_eq!(change.changed_fields[0].old_value, "\"friend\"");
+        assert_eq!(change.changed_fields[0].new_value, "null");
+        // Relation changes are not rolled back on the profile itself
+        assert!(Store::get_profile_at(owner, owner, time() - 1).is_ok());
+    }
+
+    #[test]
+    fn follows_require_a_profile_and_notify_the_followed_profile() {
+        let follower = principal(1);
+        let followed = principal(2);
+        insert_profile(follower);
+
+        assert_eq!(
+            tag(Store::follow(follower, followed).unwrap_err()),
+            "PROFILE_NOT_ON_CANISTER"
+        );
+        assert!(FOLLOWING.with(|following| following.borrow().is_empty()));
+
+        insert_profile(followed);
+        let sink = Rc::new(InMemoryNotificationSink::default());
+        Store::set_notification_sink(Some(sink.clone()));
+
+        assert!(Store::follow(follower, followed).unwrap());
+        assert_eq!(
+            Store::get_relations(followed, RelationType::Follower),
+            vec![follower]
+        );
+        assert_eq!(
+            sink.notifications.borrow_mut().pop().unwrap().1.receivers,
+            vec![followed]
+        );
+        assert_eq!(
+            tag(Store::follow(follower, followed).unwrap_err()),
+            "ALREADY_FOLLOWING"
+        );
+        assert_eq!(
+            tag(Store::follow(follower, follower).unwrap_err()),
+            "CANNOT_FOLLOW_SELF"
+        );
+
+        assert!(Store::unfollow(follower, followed).unwrap());
+        assert!(Store::get_relations(followed, RelationType::Follower).is_empty());
+        assert_eq!(
+            tag(Store::unfollow(follower, followed).unwrap_err()),
+            "NOT_FOLLOWING"
+        );
+
+        Store::block_user(followed, follower).unwrap();
+        assert_eq!(
+            tag(Store::follow(follower, followed).unwrap_err()),
+            "BLOCKED"
+        );
+    }
+
+    #[test]
+    fn expired_friend_requests_are_hidden_until_they_are_swept() {
+        let requester = principal(1);
+        let receiver = principal(2);
+        insert_profile(requester);
+        insert_profile(receiver);
+
+        let request = Store::add_friend_request(requester, receiver, "hi".to_string()).unwrap();
+        assert_eq!(Store::get_friend_requests(receiver).len(), 1);
+        FRIEND_REQUEST.with(|requests| {
+            let mut requests = requests.borrow_mut();
+            let mut _request = requests.get(&request.id).unwrap();
+            _request.created_at = time() - Store::get_friend_request_ttl();
+            requests.insert(request.id, _request);
+        });
+
+        assert!(Store::get_friend_requests(receiver).is_empty());
+        assert!(Store::get_friend_requests(requester).is_empty());
+        assert_eq!(
+            Store::get_incoming_friend_requests(receiver, 10, 1, SortDirection::Asc, false).total,
+            0
+        );
+        assert_eq!(
+            tag(Store::accept_friend_request(receiver, request.id).unwrap_err()),
+            "REQUEST_EXPIRED"
+        );
+
+        // The sweep removes the expired request and lets the requester know
+        let sink = Rc::new(InMemoryNotificationSink::default());
+        Store::set_notification_sink(Some(sink.clone()));
+        Store::expire_friend_requests();
+        assert!(FRIEND_REQUEST.with(|requests| requests.borrow().is_empty()));
+        assert_eq!(
+            sink.notifications.borrow_mut().pop().unwrap().1.receivers,
+            vec![requester]
+        );
+        assert!(Store::add_friend_request(requester, receiver, "hi".to_string()).is_ok());
+    }
+
+    #[test]
+    fn friend_request_rate_limits_are_kept_in_stable_memory_and_pruned() {
+        let requester = principal(1);
+        insert_profile(requester);
+        for n in 2..5 {
+            insert_profile(principal(n));
+        }
+        Store::set_friend_request_limits(FriendRequestLimits {
+            per_hour: 2,
+            per_day: 3,
+            max_pending: 10,
+        })
+        .unwrap();
+
+        Store::add_friend_request(requester, principal(2), "hi".to_string()).unwrap();
+        let second = Store::add_friend_request(requester, principal(3), "hi".to_string()).unwrap();
+
+        // Handled requests still count towards the limit
+        Store::decline_friend_request(principal(3), second.id).unwrap();
+        assert_eq!(
+            tag(Store::add_friend_request(requester, principal(4), "hi".to_string()).unwrap_err()),
+            "HOURLY_LIMIT_REACHED"
+        );
+
+        // Send times older than a day are pruned
+        FRIEND_REQUESTS_SENT.with(|sent| {
+            sent.borrow_mut().insert(
+                requester.to_string(),
+                SentFriendRequests {
+                    sent_on: vec![time() - DAY_IN_NANOS, time() - DAY_IN_NANOS],
+                },
+            )
+        });
+        Store::prune_friend_requests_sent();
+        assert!(FRIEND_REQUESTS_SENT.with(|sent| sent.borrow().is_empty()));
+        assert!(Store::add_friend_request(requester, principal(4), "hi".to_string()).is_ok());
+    }
+
+    #[test]
+    fn mutual_friends_and_suggestions_follow_the_relations_visibility() {
+        for n in 1..5 {
+            insert_profile(principal(n));
+        }
+        make_friends(principal(1), principal(3));
+        make_friends(principal(2), principal(3));
+        make_friends(principal(3), principal(4));
+
+        assert_eq!(
+            Store::get_mutual_friends(principal(1), principal(2)),
+            vec![principal(3)]
+        );
+        assert_eq!(Store::get_friend_suggestions(principal(1), 10).len(), 2);
+
+        // The friends of a profile that hides them are not used for mutual friends
+        Store::set_relations_visibility(principal(2), RelationsVisibility::OnlyMe).unwrap();
+        assert!(Store::get_mutual_friends(principal(1), principal(2)).is_empty());
+        assert_eq!(
+            tag(
+                Store::get_relations_count(principal(1), principal(2), RelationType::Friend)
+                    .unwrap_err()
+            ),
+            "RELATIONS_HIDDEN"
+        );
+        assert_eq!(
+            Store::get_relations_count(principal(2), principal(2), RelationType::Friend).unwrap(),
+            1
+        );
+        assert_eq!(
+            Store::get_mutual_friends(principal(2), principal(2)),
+            vec![principal(3)]
+        );
+
+        // Nor for suggestions, unless the caller is allowed to see them
+        Store::set_relations_visibility(principal(3), RelationsVisibility::OnlyMe).unwrap();
+        assert!(Store::get_friend_suggestions(principal(1), 10).is_empty());
+        Store::set_relations_visibility(principal(3), RelationsVisibility::Friends).unwrap();
+        assert_eq!(Store::get_friend_suggestions(principal(1), 10).len(), 2);
+
+        // Blocked profiles never see the friends of the profile that blocked them
+        Store::block_user(principal(3), principal(1)).unwrap();
+        assert!(Store::get_friend_suggestions(principal(1), 10).is_empty());
+    }
+
+    #[test]
+    fn clearing_relations_needs_a_valid_nonce_and_clears_both_sides() {
+        let caller = principal(1);
+        for n in 1..5 {
+            insert_profile(principal(n));
+        }
+        make_friends(caller, principal(2));
+        Store::block_user(caller, principal(3)).unwrap();
+        Store::follow(principal(4), caller).unwrap();
+        let set_nonce = |nonce: &str, expires_at: u64| {
+            CLEAR_RELATIONS_NONCES.with(|nonces| {
+                nonces
+                    .borrow_mut()
+                    .insert(caller, (nonce.to_string(), expires_at))
+            })
+        };
+
+        assert_eq!(
+            tag(block_on(Store::confirm_clear_relations(caller, "nonce".to_string())).unwrap_err()),
+            "NONCE_NOT_FOUND"
+        );
+        set_nonce("nonce", time() + CLEAR_RELATIONS_NONCE_TTL);
+        assert_eq!(
+            tag(block_on(Store::confirm_clear_relations(caller, "other".to_string())).unwrap_err()),
+            "INVALID_NONCE"
+        );
+        // The nonce is used up by the failed attempt
+        assert_eq!(
+            tag(block_on(Store::confirm_clear_relations(caller, "nonce".to_string())).unwrap_err()),
+            "NONCE_NOT_FOUND"
+        );
+        set_nonce("nonce", time() - 1);
+        assert_eq!(
+            tag(block_on(Store::confirm_clear_relations(caller, "nonce".to_string())).unwrap_err()),
+            "NONCE_EXPIRED"
+        );
+        assert!(!block_on(Store::clear_relations(
+            caller,
+            "wrong code".to_string()
+        )));
+        assert_eq!(Store::get_relations(caller, RelationType::Friend).len(), 1);
+
+        // The other child canisters can not be looked up, so the local part is returned
+        set_nonce("nonce", time() + CLEAR_RELATIONS_NONCE_TTL);
+        let summary =
+            block_on(Store::confirm_clear_relations(caller, "nonce".to_string())).unwrap();
+        assert_eq!(summary.friends, vec![principal(2)]);
+        assert_eq!(summary.blocked, vec![principal(3)]);
+        assert_eq!(summary.followers, vec![principal(4)]);
+        assert!(summary.sibling_lookup_failed);
+        assert!(Store::get_relations(principal(2), RelationType::Friend).is_empty());
+        assert!(Store::get_relations(principal(4), RelationType::Follow).is_empty());
+
+        // Only canisters are checked against the siblings known by the parent
+        assert_eq!(
+            tag(block_on(Store::clear_relations_by_sibling(principal(2), caller)).unwrap_err()),
+            "UNKNOWN_CANISTER"
+        );
+        let canister = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+        assert_eq!(
+            tag(block_on(Store::clear_relations_by_sibling(canister, caller)).unwrap_err()),
+            "ICC_GET_CANISTERS_FAILED"
+        );
+    }
+
+    #[test]
+    fn graph_check_keeps_relations_when_the_siblings_can_not_be_looked_up() {
+        let owner = principal(1);
+        let stranger = principal(9);
+        insert_profile(owner);
+        insert_profile(principal(2));
+        Store::_set_relation(
+            owner,
+            principal(2),
+            RelationType::Friend,
+            owner,
+            owner,
+            "test",
+        );
+        Store::_set_relation(owner, stranger, RelationType::Friend, owner, owner, "test");
+        Store::block_user(owner, stranger).unwrap();
+
+        // The stranger might have a profile on another child canister, so it is not dangling
+        let report = block_on(Store::check_graph_consistency(true));
+        assert!(report.dangling_relations.is_empty());
+        assert_eq!(report.asymmetric_friendships, vec![(owner, principal(2))]);
+        assert_eq!(
+            Store::get_relations(owner, RelationType::Blocked),
+            vec![stranger]
+        );
+        assert!(Store::get_relations(owner, RelationType::Friend).is_empty());
+        assert_eq!(
+            Store::get_graph_consistency_report().unwrap().checked_at,
+            time()
+        );
+    }
+
+    #[test]
+    fn hidden_fields_are_left_out_of_every_read_path() {
+        let owner = principal(1);
+        let viewer = principal(2);
+        insert_profile(owner);
+        insert_profile(viewer);
+        ENTRIES.with(|entries| {
+            let mut entries = entries.borrow_mut();
+            let mut profile = entries.get(&owner.to_string()).unwrap();
+            profile.email = "hidden@example.com".to_string();
+            entries.insert(owner.to_string(), profile);
+        });
+        Store::set_field_visibility(
+            owner,
+            vec![FieldVisibility {
+                field: ProfileField::Email,
+                audience: Audience::OnlyMe,
+            }],
+        )
+        .unwrap();
+
+        let email_filter = || vec![ProfileFilter::Email("hidden".to_string())];
+        let (bytes, _) = Store::get_chunked_data(vec![], 0, usize::MAX);
+        let chunked: Vec<ProfileResponse> = deserialize(bytes).unwrap();
+        let responses = vec![
+            Store::get_profile_by_user_principal(viewer, owner).unwrap(),
+            Store::get_profile_by_identifier(viewer, owner).unwrap(),
+            Store::get_profiles_by_user_principal(viewer, vec![owner]).remove(0),
+            Store::get_profiles_by_identifier(viewer, vec![owner]).remove(0),
+            Store::get_paged_profiles_by_principal(
+                viewer,
+                vec![owner],
+                10,
+                1,
+                vec![],
+                ProfileSort::CreatedOn(SortDirection::Asc),
+            )
+            .data
+            .remove(0),
+            Store::get_paged_profiles_by_identifier(
+                viewer,
+                vec![owner],
+                10,
+                1,
+                vec![],
+                ProfileSort::CreatedOn(SortDirection::Asc),
+            )
+            .data
+            .remove(0),
+            chunked
+                .into_iter()
+                .find(|profile| profile.principal == owner)
+                .unwrap(),
+        ];
+        for response in responses {
+            assert_eq!(response.email, "");
+        }
+        assert_eq!(
+            Store::get_profile_by_user_principal(owner, owner)
+                .unwrap()
+                .email,
+            "hidden@example.com"
+        );
+
+        // Hidden values can not be guessed with a filter
+        let (bytes, _) = Store::get_chunked_data(email_filter(), 0, usize::MAX);
+        let chunked: Vec<ProfileResponse> = deserialize(bytes).unwrap();
+        assert!(chunked.is_empty());
+        assert_eq!(
+            Store::get_paged_profiles_by_principal(
+                viewer,
+                vec![owner],
+                10,
+                1,
+                email_filter(),
+                ProfileSort::CreatedOn(SortDirection::Asc),
+            )
+            .total,
+            0
+        );
+
+        // Profiles that blocked the viewer are left out of the paged results
+        Store::block_user(owner, viewer).unwrap();
+        assert_eq!(
+            Store::get_paged_profiles_by_identifier(
+                viewer,
+                vec![owner],
+                10,
+                1,
+                vec![],
+                ProfileSort::CreatedOn(SortDirection::Asc),
+            )
+            .total,
+            0
+        );
+    }
+
+    #[test]
+    fn contact_groups_limit_fields_to_friends_in_the_group() {
+        let owner = principal(1);
+        let member = principal(2);
+        let friend = principal(3);
+        let stranger = principal(4);
+        for n in 1..=4 {
+            insert_profile(principal(n));
+        }
+        make_friends(owner, member);
+        make_friends(owner, friend);
+        ENTRIES.with(|entries| {
+            let mut entries = entries.borrow_mut();
+            let mut profile = entries.get(&owner.to_string()).unwrap();
+            profile.city = "Amsterdam".to_string();
+            entries.insert(owner.to_string(), profile);
+        });
+        let group = |name: &str, members: Vec<Principal>| PostContactGroup {
+            name: name.to_string(),
+            members,
+        };
+
+        assert_eq!(
+            tag(Store::add_contact_group(owner, group("close", vec![stranger])).unwrap_err()),
+            "NOT_A_FRIEND"
+        );
+        let close = Store::add_contact_group(owner, group("close", vec![member])).unwrap();
+        assert_eq!(
+            tag(Store::add_contact_group(owner, group("Close", vec![])).unwrap_err()),
+            "CONTACT_GROUP_EXISTS"
+        );
+
+        Store::set_field_visibility(
+            owner,
+            vec![FieldVisibility {
+                field: ProfileField::City,
+                audience: Audience::ContactGroup(close.id),
+            }],
+        )
+        .unwrap();
+        let city = |viewer: Principal| {
+            Store::get_profile_by_user_principal(viewer, owner)
+                .unwrap()
+                .city
+        };
+        assert_eq!(city(member), "Amsterdam");
+        assert_eq!(city(friend), "");
+        assert_eq!(city(stranger), "");
+
+        // Deleting the group hides the field and the id is not given to a new group
+        assert!(Store::delete_contact_group(owner, close.id).unwrap());
+        assert_eq!(
+            Store::get_field_visibility(owner)[0].audience,
+            Audience::OnlyMe
+        );
+        let other = Store::add_contact_group(owner, group("other", vec![member])).unwrap();
+        assert_ne!(other.id, close.id);
+        assert_eq!(city(member), "");
+    }
+
+    #[test]
+    fn contact_notes_are_private_and_removed_with_the_last_relation() {
+        let owner = principal(1);
+        for n in 1..5 {
+            insert_profile(principal(n));
+        }
+        let note = || PostContactNote {
+            note: "met at ICP conf".to_string(),
+            tags: vec![],
+        };
+        let has_note = |other: Principal| Store::get_contact_note(owner, other).is_ok();
+
+        make_friends(owner, principal(2));
+        Store::follow(owner, principal(2)).unwrap();
+        Store::set_contact_note(owner, principal(2), note()).unwrap();
+        Store::set_contact_note(principal(2), owner, note()).unwrap();
+
+        // Notes can only be read by their owner
+        assert_eq!(
+            tag(Store::get_contact_note(principal(3), principal(2)).unwrap_err()),
+            "CONTACT_NOTE_NOT_FOUND"
+        );
+        assert_eq!(Store::get_contact_notes(owner).len(), 1);
+        assert!(Store::get_contact_notes(principal(3)).is_empty());
+
+        // The notes stay while the follow is left, on both sides, and are removed with it
+        Store::remove_friend(owner, principal(2)).unwrap();
+        assert!(has_note(principal(2)));
+        assert!(Store::get_contact_note(principal(2), owner).is_ok());
+        Store::unfollow(owner, principal(2)).unwrap();
+        assert!(!has_note(principal(2)));
+        assert!(Store::get_contact_note(principal(2), owner).is_err());
+
+        Store::block_user(owner, principal(3)).unwrap();
+        Store::set_contact_note(owner, principal(3), note()).unwrap();
+        Store::unblock_user(owner, principal(3)).unwrap();
+        assert!(!has_note(principal(3)));
+
+        Store::mute(owner, principal(4)).unwrap();
+        Store::set_contact_note(owner, principal(4), note()).unwrap();
+        Store::unmute(owner, principal(4)).unwrap();
+        assert!(!has_note(principal(4)));
+
+        make_friends(owner, principal(2));
+        Store::set_contact_note(owner, principal(2), note()).unwrap();
+        Store::set_contact_note(principal(2), owner, note()).unwrap();
+        Store::_clear_relations_locally(owner);
+        assert!(Store::get_contact_notes(owner).is_empty());
+        assert!(Store::get_contact_notes(principal(2)).is_empty());
+    }
+
+    #[test]
+    fn incoming_and_outgoing_friend_requests_are_paged_and_sorted_on_creation_date() {
+        let owner = principal(1);
+        let receiver = principal(2);
+        for n in 1..=5 {
+            insert_profile(principal(n));
+        }
+        // Requests to the owner, created in a different order than their ids
+        let mut incoming = vec![];
+        for (n, age) in [(3, 30), (4, 10), (5, 20)] {
+            let request = Store::add_friend_request(principal(n), owner, "hi".to_string()).unwrap();
+            FRIEND_REQUEST.with(|requests| {
+                let mut requests = requests.borrow_mut();
+                let mut _request = requests.get(&request.id).unwrap();
+                _request.created_at = time() - age;
+                requests.insert(request.id, _request);
+            });
+            incoming.push(request.id);
+        }
+        let outgoing = Store::add_friend_request(owner, receiver, "hi".to_string()).unwrap();
+
+        let ids = |response: PagedResponse<FriendRequestWithProfileResponse>| -> Vec<u64> {
+            response.data.iter().map(|r| r.request.id).collect()
+        };
+
+        // Only the requests in the asked direction are returned
+        let response = Store::get_outgoing_friend_requests(owner, 10, 1, SortDirection::Asc, false);
+        assert_eq!(ids(response), vec![outgoing.id]);
+        assert!(
+            Store::get_incoming_friend_requests(receiver, 10, 1, SortDirection::Asc, false)
+                .data
+                .iter()
+                .all(|r| r.request.requested_by == owner)
+        );
+
+        let (oldest, newest, middle) = (incoming[0], incoming[1], incoming[2]);
+        let response = Store::get_incoming_friend_requests(owner, 10, 1, SortDirection::Asc, false);
+        assert_eq!(ids(response), vec![oldest, middle, newest]);
+        let response =
+            Store::get_incoming_friend_requests(owner, 10, 1, SortDirection::Desc, false);
+        assert_eq!(ids(response), vec![newest, middle, oldest]);
+
+        // Pages start at one and the last page holds the rest
+        let first_page =
+            Store::get_incoming_friend_requests(owner, 2, 1, SortDirection::Asc, false);
+        assert_eq!(first_page.total, 3);
+        assert_eq!(first_page.number_of_pages, 2);
+        assert_eq!(ids(first_page), vec![oldest, middle]);
+        let second_page =
+            Store::get_incoming_friend_requests(owner, 2, 2, SortDirection::Asc, false);
+        assert_eq!(ids(second_page), vec![newest]);
+
+        // The profile summary of the other party is only loaded on request
+        let response = Store::get_incoming_friend_requests(owner, 10, 1, SortDirection::Asc, false);
+        assert!(response.data.iter().all(|r| r.profile.is_none()));
+        let response = Store::get_incoming_friend_requests(owner, 10, 1, SortDirection::Asc, true);
+        let summary = response.data[0].profile.clone().unwrap();
+        assert_eq!(summary.principal, principal(3));
+        assert_eq!(summary.username, "user3");
+        let response = Store::get_outgoing_friend_requests(owner, 10, 1, SortDirection::Asc, true);
+        assert_eq!(
+            response.data[0].profile.clone().unwrap().principal,
+            receiver
+        );
+    }
+
+    #[test]
+    fn recommendations_are_ranked_on_overlap_and_leave_out_relations_and_hidden_locations() {
+        let owner = principal(1);
+        let set_profile = |n: u8, skills: Vec<u32>, country: &str| {
+            insert_profile(principal(n));
+            ENTRIES.with(|entries| {
+                let mut entries = entries.borrow_mut();
+                let mut profile = entries.get(&principal(n).to_string()).unwrap();
+                profile.skills = skills;
+                profile.country = country.to_string();
+                entries.insert(principal(n).to_string(), profile);
+            });
+        };
+        set_profile(1, vec![1, 2], "NL");
+        set_profile(2, vec![1, 2], "");
+        set_profile(3, vec![1], "");
+        set_profile(4, vec![1, 2], "NL");
+        set_profile(5, vec![1, 2], "NL");
+        set_profile(6, vec![1, 2], "NL");
+        set_profile(7, vec![], "NL");
+        set_profile(8, vec![], "nl");
+        make_friends(owner, principal(4));
+        Store::block_user(owner, principal(5)).unwrap();
+        Store::mute(owner, principal(6)).unwrap();
+        Store::set_field_visibility(
+            principal(7),
+            vec![FieldVisibility {
+                field: ProfileField::Country,
+                audience: Audience::OnlyMe,
+            }],
+        )
+        .unwrap();
+
+        let recommendations = Store::recommend_profiles(owner, 10).unwrap();
+        let principals: Vec<Principal> = recommendations
+            .iter()
+            .map(|recommendation| recommendation.profile.principal)
+            .collect();
+        // Friends, blocked and muted users are left out, and a hidden country does not count
+        assert_eq!(principals, vec![principal(2), principal(3), principal(8)]);
+        assert!(recommendations[0].score > recommendations[1].score);
+        assert!(recommendations[1].score > recommendations[2].score);
+
+        assert_eq!(
+            Store::recommend_profiles(owner, 1)
+                .unwrap()
+                .iter()
+                .map(|recommendation| recommendation.profile.principal)
+                .collect::<Vec<Principal>>(),
+            vec![principal(2)]
+        );
+        assert_eq!(
+            tag(Store::recommend_profiles(principal(9), 10).unwrap_err()),
+            "PROFILE_NOT_FOUND"
+        );
+    }
+
+    #[test]
+    fn relations_are_paged_with_a_cursor_and_respect_the_relations_visibility() {
+        let owner = principal(1);
+        let stranger = principal(6);
+        for n in 1..=6 {
+            insert_profile(principal(n));
+        }
+        for n in 2..=5 {
+            make_friends(owner, principal(n));
+        }
+        let page = |caller: Principal, cursor: Option<Principal>| {
+            Store::get_relations_paged(
+                caller,
+                owner,
+                RelationType::Friend,
+                2,
+                cursor,
+                RelationSort::Principal(SortDirection::Asc),
+            )
+        };
+        let principals = |response: &CursorPagedRelationsResponse| -> Vec<Principal> {
+            response.data.iter().map(|r| r.relation.principal).collect()
+        };
+
+        let first_page = page(stranger, None).unwrap();
+        assert_eq!(principals(&first_page), vec![principal(2), principal(3)]);
+        assert_eq!(first_page.next_cursor, Some(principal(3)));
+        let last_page = page(stranger, first_page.next_cursor).unwrap();
+        assert_eq!(principals(&last_page), vec![principal(4), principal(5)]);
+        assert_eq!(last_page.next_cursor, None);
+
+        assert_eq!(
+            tag(page(stranger, Some(principal(9))).unwrap_err()),
+            "INVALID_CURSOR"
+        );
+
+        // Relations with principals of which the profile is gone are left out
+        ENTRIES.with(|entries| entries.borrow_mut().remove(&principal(5).to_string()));
+        let last_page = page(stranger, Some(principal(3))).unwrap();
+        assert_eq!(principals(&last_page), vec![principal(4)]);
+        assert_eq!(last_page.next_cursor, None);
+
+        Store::set_relations_visibility(owner, RelationsVisibility::Friends).unwrap();
+        assert_eq!(tag(page(stranger, None).unwrap_err()), "RELATIONS_HIDDEN");
+        assert!(page(principal(2), None).is_ok());
+        assert!(page(owner, None).is_ok());
+
+        Store::set_relations_visibility(owner, RelationsVisibility::OnlyMe).unwrap();
+        assert_eq!(tag(page(stranger, None).unwrap_err()), "RELATIONS_HIDDEN");
+        assert_eq!(
+            tag(page(principal(2), None).unwrap_err()),
+            "RELATIONS_HIDDEN"
+        );
+        assert_eq!(principals(&page(owner, None).unwrap()).len(), 2);
+    }
+
+    #[test]
+    fn failed_notifications_are_retried_with_backoff() {
+        Store::set_notification_environment(NotificationEnvironment::Production);
+        Store::_notify(
+            principal(1),
+            SendNotificationData {
+                data: NotificationType::FriendRemove(principal(2)),
+                receivers: vec![principal(2)],
+            },
+            "{}".to_string(),
+        );
+
+        let due = Store::_take_due_outbox_notifications(time(), 10);
+        assert_eq!(due.len(), 1);
+        // Notifications that are being delivered are not due again
+        assert!(Store::_take_due_outbox_notifications(time(), 10).is_empty());
+
+        let id = due[0].0;
+        for attempt in 1..NOTIFICATION_MAX_ATTEMPTS {
+            Store::_complete_outbox_notification(id, Err("rejected".to_string()));
+            let stuck = Store::get_stuck_notifications(10, 1).data;
+            assert_eq!(stuck[0].notification.attempts, attempt);
+            assert_eq!(
+                stuck[0].notification.next_attempt_at,
+                time() + NOTIFICATION_RETRY_BASE_DELAY * 2u64.pow(attempt - 1)
+            );
+        }
+
+        Store::_complete_outbox_notification(id, Err("rejected".to_string()));
+        assert!(Store::get_stuck_notifications(10, 1).data.is_empty());
+        assert_eq!(Store::get_notification_dead_letters(10, 1).total, 1);
+
+        assert!(Store::retry_dead_letter_notification(id).unwrap());
+        assert_eq!(Store::get_notification_dead_letters(10, 1).total, 0);
+        Store::_complete_outbox_notification(id, Ok(()));
+        assert!(Store::_take_due_outbox_notifications(u64::MAX, 10).is_empty());
+    }
+
+    #[test]
+    fn outbox_notifications_are_delivered_to_the_method_of_their_kind() {
+        Store::set_notification_environment(NotificationEnvironment::Production);
+        let data = || SendNotificationData {
+            data: NotificationType::FriendRemove(principal(2)),
+            receivers: vec![principal(2)],
+        };
+        Store::_notify(principal(1), data(), "sent".to_string());
+        Store::_notify_silent(data(), "silent".to_string());
+        let notifications = Store::_take_due_outbox_notifications(time(), 10);
+
+        let (method, args) =
+            OutboxNotificationSink::get_delivery_call(&notifications[0].1).unwrap();
+        assert_eq!(method, ADD_NOTIFICATION_METHOD);
+        let (sender, _data, metadata): (Principal, SendNotificationData, String) =
+            candid::decode_args(&args).unwrap();
+        assert_eq!(sender, principal(1));
+        assert_eq!(_data.receivers, vec![principal(2)]);
+        assert_eq!(metadata, "sent");
+
+        // Silent notifications have no sender and go to the silent method
+        let (method, args) =
+            OutboxNotificationSink::get_delivery_call(&notifications[1].1).unwrap();
+        assert_eq!(method, SILENT_NOTIFICATION_METHOD);
+        let (_data, metadata): (SendNotificationData, String) = candid::decode_args(&args).unwrap();
+        assert_eq!(_data.receivers, vec![principal(2)]);
+        assert_eq!(metadata, "silent");
+    }
+
+    #[test]
+    fn outbox_ids_are_not_reused() {
+        Store::set_notification_environment(NotificationEnvironment::Production);
+        let notify = || {
+            Store::_notify(
+                principal(1),
+                SendNotificationData {
+                    data: NotificationType::FriendRemove(principal(2)),
+                    receivers: vec![principal(2)],
+                },
+                "{}".to_string(),
+            )
+        };
+
+        notify();
+        let id = Store::_take_due_outbox_notifications(time(), 10)[0].0;
+        for _ in 0..NOTIFICATION_MAX_ATTEMPTS {
+            Store::_complete_outbox_notification(id, Err("rejected".to_string()));
+        }
+        assert_eq!(Store::get_notification_dead_letters(10, 1).total, 1);
+
+        // The outbox is empty, but the next notification does not get the id of the dead letter
+        notify();
+        let next_id = Store::_take_due_outbox_notifications(time(), 10)[0].0;
+        assert!(next_id > id);
+
+        // A dead letter can not be moved back over a notification with the same id
+        let notification =
+            NOTIFICATION_OUTBOX.with(|outbox| outbox.borrow().get(&next_id).unwrap());
+        NOTIFICATION_OUTBOX.with(|outbox| outbox.borrow_mut().insert(id, notification));
+        assert_eq!(
+            tag(Store::retry_dead_letter_notification(id).unwrap_err()),
+            "ID_IN_OUTBOX"
+        );
+        assert_eq!(Store::get_notification_dead_letters(10, 1).total, 1);
+    }
+
+    #[test]
+    fn disabled_notifications_are_recorded_locally() {
+        Store::set_notification_environment(NotificationEnvironment::Production);
+        assert!(Store::get_environment().is_ok());
+
+        Store::set_notification_environment(NotificationEnvironment::Disabled);
+        Store::_notify_silent(
+            SendNotificationData {
+                data: NotificationType::FriendRemove(principal(2)),
+                receivers: vec![principal(2)],
+            },
+            "{}".to_string(),
+        );
+
+        let notifications = Store::get_local_notifications(10, 1).data;
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].sender, None);
+        assert!(notifications[0].notification_type.contains("FriendRemove"));
+    }
+
+    #[test]
+    fn stored_profiles_are_re_encoded_in_batches() {
+        for n in 1..=3 {
+            insert_profile(principal(n));
+        }
+
+        assert!(!Store::migrate_profiles_batch(2));
+        assert!(PROFILE_MIGRATION_CURSOR.with(|cursor| cursor.borrow().is_some()));
+        assert!(Store::migrate_profiles_batch(2));
+        assert!(PROFILE_MIGRATION_CURSOR.with(|cursor| cursor.borrow().is_none()));
+
+        let profiles = ENTRIES.with(Data::get_entries);
+        assert_eq!(profiles.len(), 3);
+        assert!(profiles
+            .iter()
+            .all(|(_, profile)| profile.display_name == "Test profile"));
+    }
+
+    #[test]
+    fn blocked_principals_can_not_see_the_profile_or_send_friend_requests() {
+        let owner = principal(1);
+        let blocked = principal(2);
+        insert_profile(owner);
+        insert_profile(blocked);
+        FRIEND_REQUEST.with(|requests| {
+            requests.borrow_mut().insert(
+                1,
+                FriendRequest {
+                    requested_by: blocked,
+                    message: "hi".to_string(),
+                    to: owner,
+                    created_at: time(),
+                },
+            )
+        });
+
+        // Blocking cancels the pending friend requests in both directions
+        Store::block_user(owner, blocked).unwrap();
+        assert!(FRIEND_REQUEST.with(|requests| requests.borrow().is_empty()));
+
+        assert_eq!(
+            tag(Store::get_profile_by_user_principal(blocked, owner).unwrap_err()),
+            "PROFILE_NOT_FOUND"
+        );
+        assert!(Store::get_profile_by_user_principal(owner, blocked).is_ok());
+        assert!(Store::get_profiles_by_user_principal(blocked, vec![owner]).is_empty());
+        for (requested_by, to) in [(blocked, owner), (owner, blocked)] {
+            assert_eq!(
+                tag(Store::add_friend_request(requested_by, to, "hi".to_string()).unwrap_err()),
+                "BLOCKED"
+            );
+        }
+    }
+
+    #[test]
+    fn graph_check_reports_and_repairs_asymmetric_friendships() {
+        for n in 1..=3 {
+            insert_profile(principal(n));
+        }
+        let befriend = |owner: Principal, other: Principal| {
+            RELATIONS.with(|relations| {
+                relations.borrow_mut().insert(
+                    RelationKey { owner, other },
+                    Relation {
+                        kind: RelationType::Friend,
+                        since: time(),
+                        initiated_by: owner,
+                    },
+                )
+            })
+        };
+        befriend(principal(1), principal(2));
+        befriend(principal(1), principal(3));
+        befriend(principal(3), principal(1));
+        FRIEND_REQUEST.with(|requests| {
+            requests.borrow_mut().insert(
+                1,
+                FriendRequest {
+                    requested_by: principal(3),
+                    message: "hi".to_string(),
+                    to: principal(1),
+                    created_at: time(),
+                },
+            )
+        });
+
+        // Without repairing the graph is only reported
+        let report = block_on(Store::check_graph_consistency(false));
+        assert_eq!(
+            report.asymmetric_friendships,
+            vec![(principal(1), principal(2))]
+        );
+        assert!(report.dangling_relations.is_empty());
+        assert_eq!(report.redundant_friend_requests, vec![1]);
+        assert_eq!(
+            Store::get_relations(principal(1), RelationType::Friend).len(),
+            2
+        );
+
+        block_on(Store::check_graph_consistency(true));
+        assert_eq!(
+            Store::get_relations(principal(1), RelationType::Friend),
+            vec![principal(3)]
+        );
+        assert!(FRIEND_REQUEST.with(|requests| requests.borrow().is_empty()));
+        assert!(Store::get_graph_consistency_report().unwrap().repaired);
+    }
+
+    #[test]
+    fn the_notification_environment_is_set_by_the_admin_config() {
+        // Unit tests do not run on one of the known canisters, so `Auto` finds no environment
+        assert_eq!(
+            Store::get_notification_environment(),
+            NotificationEnvironment::Auto
+        );
+        assert_eq!(
+            tag(Store::get_environment().unwrap_err()),
+            "ENVIRONMENT_NOT_FOUND"
+        );
+
+        for environment in [
+            NotificationEnvironment::Production,
+            NotificationEnvironment::Staging,
+            NotificationEnvironment::Development,
+        ] {
+            assert_eq!(
+                Store::set_notification_environment(environment.clone()),
+                environment
+            );
+            assert_eq!(Store::get_notification_environment(), environment);
+            assert_eq!(
+                format!("{:?}", Store::get_environment().unwrap()),
+                format!("{:?}", environment)
+            );
+        }
+
+        Store::set_notification_environment(NotificationEnvironment::Disabled);
+        assert_eq!(
+            tag(Store::get_environment().unwrap_err()),
+            "ENVIRONMENT_NOT_FOUND"
+        );
     }
 }
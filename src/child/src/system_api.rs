@@ -0,0 +1,84 @@
+use candid::Principal;
+use ic_cdk::api::call::CallResult;
+use ic_scalable_canister::ic_scalable_misc::{
+    enums::api_error_type::{ApiError, ApiErrorType},
+    models::{canister_models::ScalableCanisterDetails, error_message_models::ErrorMessage},
+};
+use ic_scalable_canister::store::Data;
+
+// The parts of the system API that the store uses,
+// in unit tests there is no replica so they return fixed values instead of trapping
+#[cfg(not(test))]
+pub use ic_cdk::{
+    api::{is_controller, time},
+    id,
+};
+
+#[cfg(test)]
+pub fn time() -> u64 {
+    1_700_000_000_000_000_000
+}
+
+#[cfg(test)]
+pub fn is_controller(_principal: &Principal) -> bool {
+    false
+}
+
+// Not one of the known canisters, so tests run against an unknown environment
+#[cfg(test)]
+pub fn id() -> Principal {
+    Principal::anonymous()
+}
+
+// Method to get the child canisters from the parent canister
+#[cfg(not(test))]
+pub async fn get_canisters(parent: Principal) -> CallResult<(Vec<ScalableCanisterDetails>,)> {
+    ic_cdk::api::call::call(parent, "get_canisters", ()).await
+}
+
+// There is no parent canister in unit tests, so the call is rejected like an unreachable parent
+#[cfg(test)]
+pub async fn get_canisters(_parent: Principal) -> CallResult<(Vec<ScalableCanisterDetails>,)> {
+    Err((
+        ic_cdk::api::call::RejectionCode::DestinationInvalid,
+        "No parent canister in unit tests".to_string(),
+    ))
+}
+
+// Same as `error_helper::api_error`, but uses the `id` of this module for the location,
+// the helper of the library calls `ic_cdk::id` which traps outside of a canister, so it can not be used in unit tests
+pub fn api_error(
+    kind: ApiErrorType,
+    tag: &str,
+    message: &str,
+    name: &str,
+    method_name: &str,
+    inputs: Option<Vec<String>>,
+) -> ApiError {
+    let error_message = ErrorMessage {
+        tag: tag.to_string(),
+        message: message.to_string(),
+        location: format!("{}/{}/{}", id(), name, method_name),
+        inputs,
+    };
+
+    match kind {
+        ApiErrorType::Unauthorized => ApiError::Unauthorized(error_message),
+        ApiErrorType::NotFound => ApiError::NotFound(error_message),
+        ApiErrorType::BadRequest => ApiError::BadRequest(error_message),
+        _ => ApiError::Unexpected(error_message),
+    }
+}
+
+// Same as `Data::default`, but uses the `time` of this module
+pub fn default_data() -> Data {
+    Data {
+        name: String::default(),
+        identifier: 0,
+        current_entry_id: 0,
+        parent: Principal::anonymous(),
+        is_available: bool::default(),
+        updated_at: time(),
+        created_at: time(),
+    }
+}
@@ -4,14 +4,19 @@ use ic_scalable_canister::ic_scalable_misc::{
     models::validation_models::ValidateField,
 };
 
-use shared::profile_models::{PostProfile, UpdateProfile};
+use shared::profile_models::{PostContactGroup, PostContactNote, PostProfile, UpdateProfile};
+
+// The rule for usernames, shared by new profiles and username changes
+fn username_field(username: String) -> ValidateField {
+    ValidateField(
+        ValidationType::StringLength(username, 3, 64),
+        "username".to_string(),
+    )
+}
 
 pub fn validate_post_profile(post_profile: PostProfile) -> Result<(), ApiError> {
     let validator_fields = vec![
-        ValidateField(
-            ValidationType::StringLength(post_profile.username, 3, 64),
-            "username".to_string(),
-        ),
+        username_field(post_profile.username),
         ValidateField(
             ValidationType::StringLength(post_profile.display_name, 3, 64),
             "display_name".to_string(),
@@ -67,3 +72,44 @@ pub fn validate_update_profile(update_profile: UpdateProfile) -> Result<(), ApiE
 
     Validator(validator_fields).validate()
 }
+
+pub fn validate_username(username: String) -> Result<(), ApiError> {
+    Validator(vec![username_field(username)]).validate()
+}
+
+pub fn validate_post_contact_group(post_contact_group: PostContactGroup) -> Result<(), ApiError> {
+    let validator_fields = vec![
+        ValidateField(
+            ValidationType::StringLength(post_contact_group.name, 1, 64),
+            "name".to_string(),
+        ),
+        ValidateField(
+            ValidationType::Count(post_contact_group.members.len(), 0, 500),
+            "members".to_string(),
+        ),
+    ];
+
+    Validator(validator_fields).validate()
+}
+
+pub fn validate_post_contact_note(post_contact_note: PostContactNote) -> Result<(), ApiError> {
+    let mut validator_fields = vec![
+        ValidateField(
+            ValidationType::StringLength(post_contact_note.note, 0, 1000),
+            "note".to_string(),
+        ),
+        ValidateField(
+            ValidationType::Count(post_contact_note.tags.len(), 0, 20),
+            "tags".to_string(),
+        ),
+    ];
+
+    for tag in post_contact_note.tags {
+        validator_fields.push(ValidateField(
+            ValidationType::StringLength(tag, 1, 32),
+            "tags".to_string(),
+        ));
+    }
+
+    Validator(validator_fields).validate()
+}
@@ -1,9 +1,9 @@
-use ic_cdk::query;
+use ic_cdk::{caller, query};
 use ic_scalable_misc::{
     enums::filter_type::FilterType, models::paged_response_models::PagedResponse,
 };
 
-use shared::profile_models::{ProfileFilter, ProfileResponse, ProfileSort};
+use shared::profile_models::{ProfileFilter, ProfileRecommendation, ProfileResponse, ProfileSort};
 
 use super::store::ScalableData;
 
@@ -19,3 +19,11 @@ async fn get_profiles(
 ) -> PagedResponse<ProfileResponse> {
     ScalableData::get_child_canister_data(limit, page, filters, filter_type, sort).await
 }
+
+// Method used to get the recommended profiles for the caller from all the child canisters,
+// the profiles are scored on the child canisters and merged on the highest score
+// requires composite queries to be released to mainnet
+#[query(composite = true)]
+async fn recommend_profiles(limit: usize) -> Vec<ProfileRecommendation> {
+    ScalableData::get_child_canister_recommendations(caller(), limit).await
+}
@@ -29,7 +29,9 @@ use ic_scalable_misc::{
     },
 };
 
-use shared::profile_models::{ProfileFilter, ProfileResponse, ProfileSort};
+use shared::profile_models::{
+    ProfileFilter, ProfileRecommendation, ProfileResponse, ProfileSort, RecommendationSource,
+};
 
 #[derive(CandidType, Clone, Deserialize)]
 pub struct ScalableMetaData {
@@ -565,6 +567,59 @@ impl ScalableData {
         }
     }
 
+    // Method to get the recommended profiles for a principal from all the child canisters
+    pub async fn get_child_canister_recommendations(
+        principal: Principal,
+        limit: usize,
+    ) -> Vec<ProfileRecommendation> {
+        let canisters: Vec<Principal> = DATA.with(|data| {
+            data.borrow()
+                .canisters
+                .values()
+                .map(|c| c.principal)
+                .collect()
+        });
+
+        // Find the canister that holds the profile of the principal to get the source to score against
+        let mut source: Option<RecommendationSource> = None;
+        for canister in canisters.iter() {
+            let result: Result<(Option<RecommendationSource>,), _> = call::call(
+                *canister,
+                "get_recommendation_source_by_parent",
+                (principal,),
+            )
+            .await;
+
+            if let Ok((Some(_source),)) = result {
+                source = Some(_source);
+                break;
+            }
+        }
+
+        let source = match source {
+            None => return vec![],
+            Some(_source) => _source,
+        };
+
+        let mut recommendations: Vec<ProfileRecommendation> = vec![];
+        for canister in canisters {
+            let result: Result<(Vec<ProfileRecommendation>,), _> = call::call(
+                canister,
+                "get_recommended_profiles_by_parent",
+                (source.clone(), limit),
+            )
+            .await;
+
+            if let Ok((mut _recommendations,)) = result {
+                recommendations.append(&mut _recommendations);
+            }
+        }
+
+        recommendations.sort_by(|a, b| b.score.total_cmp(&a.score));
+        recommendations.truncate(limit);
+        recommendations
+    }
+
     fn get_name() -> String {
         DATA.with(|v| v.borrow().name.clone())
     }
@@ -1 +1,2 @@
 pub mod profile_models;
+pub mod profile_storage;
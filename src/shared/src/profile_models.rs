@@ -10,6 +10,8 @@ use ic_scalable_misc::{
 use ic_stable_structures::{storable::Bound, Storable};
 use serde::Serialize;
 
+use crate::profile_storage::{decode_profile, encode_profile};
+
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct Profile {
     pub principal: Principal,
@@ -37,21 +39,24 @@ pub struct Profile {
     pub terms_of_service: Option<DocumentDetails>,
     pub wallets: HashMap<Principal, Wallet>,
     pub starred: HashMap<Principal, String>,
+    // Deprecated, relations are stored as `Relation` per pair, this map is only read to migrate old profiles
     pub relations: HashMap<Principal, String>,
     pub extra: String,
+    pub notification_preferences: NotificationPreferences,
     pub updated_on: u64,
     pub created_on: u64,
 }
 
 impl StableStorableTrait for Profile {}
 
+// Profiles are stored in a versioned envelope, see `profile_storage`
 impl Storable for Profile {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+        Cow::Owned(encode_profile(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_profile(bytes.as_ref())
     }
 
     const BOUND: Bound = Bound::Unbounded;
@@ -85,6 +90,7 @@ impl Default for Profile {
             starred: Default::default(),
             relations: Default::default(),
             extra: Default::default(),
+            notification_preferences: Default::default(),
             updated_on: Default::default(),
             created_on: Default::default(),
             privacy_policy: None,
@@ -200,6 +206,397 @@ pub enum ProfilePrivacy {
 pub enum RelationType {
     Friend,
     Blocked,
+    // The principals the profile follows
+    Follow,
+    // The principals that follow the profile, the reverse side of `Follow`
+    Follower,
+    // The principals the profile muted, only visible to the profile itself
+    Muted,
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct Relation {
+    pub kind: RelationType,
+    pub since: u64,
+    pub initiated_by: Principal,
+}
+
+impl Storable for Relation {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of a relation, stored as the length prefixed owner followed by the other principal
+// so all relations of an owner are next to each other in the map
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RelationKey {
+    pub owner: Principal,
+    pub other: Principal,
+}
+
+impl RelationKey {
+    // The smallest key of an owner, used as the start of a range over the relations of the owner
+    pub fn first_of(owner: Principal) -> Self {
+        Self {
+            owner,
+            other: Principal::from_slice(&[]),
+        }
+    }
+}
+
+impl Storable for RelationKey {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        let owner = self.owner.as_slice();
+        let mut bytes = vec![owner.len() as u8];
+        bytes.extend_from_slice(owner);
+        bytes.extend_from_slice(self.other.as_slice());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let owner_length = bytes[0] as usize;
+        Self {
+            owner: Principal::from_slice(&bytes[1..owner_length + 1]),
+            other: Principal::from_slice(&bytes[owner_length + 1..]),
+        }
+    }
+
+    // A principal is at most 29 bytes
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1 + 29 + 29,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RelationResponse {
+    pub principal: Principal,
+    pub kind: RelationType,
+    pub since: u64,
+    pub initiated_by: Principal,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RelationWithProfileResponse {
+    pub relation: RelationResponse,
+    pub profile: ProfileSummary,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CursorPagedRelationsResponse {
+    pub data: Vec<RelationWithProfileResponse>,
+    // Principal of the last returned relation, pass it as the cursor to get the next page
+    pub next_cursor: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ClearRelationsRequest {
+    // Nonce that has to be passed to `confirm_clear_relations` before it expires
+    pub nonce: String,
+    pub expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ClearRelationsSummary {
+    pub friends: Vec<Principal>,
+    pub blocked: Vec<Principal>,
+    pub following: Vec<Principal>,
+    pub followers: Vec<Principal>,
+    // Child canisters that could not be reached, the relations stored on these canisters are not removed
+    pub failed_canisters: Vec<Principal>,
+    // Set when the other child canisters could not be looked up, only the relations on this canister are removed
+    pub sibling_lookup_failed: bool,
+}
+
+impl ClearRelationsSummary {
+    pub fn merge(&mut self, other: ClearRelationsSummary) {
+        let merge = |into: &mut Vec<Principal>, from: Vec<Principal>| {
+            for principal in from {
+                if !into.contains(&principal) {
+                    into.push(principal);
+                }
+            }
+        };
+        merge(&mut self.friends, other.friends);
+        merge(&mut self.blocked, other.blocked);
+        merge(&mut self.following, other.following);
+        merge(&mut self.followers, other.followers);
+        merge(&mut self.failed_canisters, other.failed_canisters);
+        self.sibling_lookup_failed |= other.sibling_lookup_failed;
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct GraphConsistencyReport {
+    pub checked_at: u64,
+    // Friendships (owner, other) that are not stored on the side of the other principal
+    pub asymmetric_friendships: Vec<(Principal, Principal)>,
+    // Relations, follows and mutes (owner, other) of which one of both principals has no profile on any child canister
+    pub dangling_relations: Vec<(Principal, Principal)>,
+    // Ids of friend requests between principals that are already friends
+    pub redundant_friend_requests: Vec<u64>,
+    // If the found issues were repaired
+    pub repaired: bool,
+}
+
+// A named list of friends of a profile, for example "close friends" or "colleagues"
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ContactGroup {
+    pub owner: Principal,
+    pub name: String,
+    pub members: Vec<Principal>,
+    pub created_on: u64,
+    pub updated_on: u64,
+}
+
+impl Storable for ContactGroup {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PostContactGroup {
+    pub name: String,
+    pub members: Vec<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ContactGroupResponse {
+    pub id: u64,
+    pub name: String,
+    pub members: Vec<Principal>,
+    pub created_on: u64,
+    pub updated_on: u64,
+}
+
+// A private note and tags a profile keeps about one of its relations, only visible to the owner
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ContactNote {
+    pub note: String,
+    pub tags: Vec<String>,
+    pub created_on: u64,
+    pub updated_on: u64,
+}
+
+impl Storable for ContactNote {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PostContactNote {
+    pub note: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ContactNoteResponse {
+    pub principal: Principal,
+    pub note: String,
+    pub tags: Vec<String>,
+    pub created_on: u64,
+    pub updated_on: u64,
+}
+
+// The fields of a profile of which the visibility can be limited
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ProfileField {
+    FirstName,
+    LastName,
+    Email,
+    DateOfBirth,
+    About,
+    City,
+    StateOrProvince,
+    Country,
+    Website,
+}
+
+// Who can see a field of a profile, the profile itself can always see all fields
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Audience {
+    Public,
+    Friends,
+    // The members of a contact group of the profile, by the id of the group
+    ContactGroup(u64),
+    OnlyMe,
+}
+
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize)]
+pub struct FieldVisibility {
+    pub field: ProfileField,
+    pub audience: Audience,
+}
+
+// The field visibility of a profile, fields that are not in the list are public
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProfileFieldVisibility {
+    pub fields: Vec<FieldVisibility>,
+}
+
+impl Storable for ProfileFieldVisibility {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Who can see the friends, follows and followers of a profile, blocks and mutes are only visible to the profile itself
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum RelationsVisibility {
+    #[default]
+    Public,
+    Friends,
+    OnlyMe,
+}
+
+impl Storable for RelationsVisibility {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Where the notifications of the canister are send to, `Auto` picks the environment based on the canister id
+// and records the notifications locally when the canister is not one of the known canisters (for example local dfx deployments)
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum NotificationEnvironment {
+    #[default]
+    Auto,
+    Production,
+    Staging,
+    Development,
+    Disabled,
+}
+
+impl Storable for NotificationEnvironment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Whether a profile receives the notifications of an event
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum NotificationPreference {
+    #[default]
+    On,
+    Off,
+}
+
+// The notifications a profile receives per event, all events are on by default
+#[derive(CandidType, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct NotificationPreferences {
+    // A friend request was received
+    pub friend_request: NotificationPreference,
+    // A send friend request was accepted
+    pub friend_request_accepted: NotificationPreference,
+    // A send friend request was declined
+    pub friend_request_declined: NotificationPreference,
+    // A send friend request expired before it was answered
+    pub friend_request_expired: NotificationPreference,
+    // A friend removed the profile as friend
+    pub friend_removed: NotificationPreference,
+    // A profile started following the profile
+    pub new_follower: NotificationPreference,
+}
+
+// A notification that is recorded on the canister instead of being send to the notification canister
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct LocalNotification {
+    // Not set for silent notifications
+    pub sender: Option<Principal>,
+    pub receivers: Vec<Principal>,
+    // The json encoded notification type
+    pub notification_type: String,
+    pub metadata: String,
+    pub created_at: u64,
+}
+
+impl Storable for LocalNotification {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A notification waiting to be delivered to the notification canister, or a dead letter when all attempts failed
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct OutboxNotification {
+    // Not set for silent notifications
+    pub sender: Option<Principal>,
+    // The json encoded notification data
+    pub data: String,
+    pub metadata: String,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+    pub created_at: u64,
+}
+
+impl Storable for OutboxNotification {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Debug, Clone, Deserialize)]
+pub struct OutboxNotificationResponse {
+    pub id: u64,
+    pub notification: OutboxNotification,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RelationSort {
+    Since(SortDirection),
+    Principal(SortDirection),
 }
 
 impl fmt::Display for RelationType {
@@ -208,6 +605,20 @@ impl fmt::Display for RelationType {
         match self {
             Friend => write!(f, "friend"),
             Blocked => write!(f, "blocked"),
+            Follow => write!(f, "follow"),
+            Follower => write!(f, "follower"),
+            Muted => write!(f, "muted"),
+        }
+    }
+}
+
+impl RelationType {
+    // Method to parse the string value used by the deprecated `Profile.relations` map
+    pub fn from_legacy(value: &str) -> Option<Self> {
+        match value {
+            "friend" => Some(RelationType::Friend),
+            "blocked" => Some(RelationType::Blocked),
+            _ => None,
         }
     }
 }
@@ -269,6 +680,117 @@ impl Storable for FriendRequest {
     const BOUND: Bound = Bound::Unbounded;
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FriendSuggestion {
+    pub principal: Principal,
+    // Number of friends the suggested principal has in common with the caller
+    pub mutual_friends: u64,
+}
+
+// The taxonomy and location of a profile that other profiles are scored against for recommendations
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RecommendationSource {
+    pub principal: Principal,
+    pub skills: Vec<u32>,
+    pub interests: Vec<u32>,
+    pub causes: Vec<u32>,
+    pub country: String,
+    pub state_or_province: String,
+    // Principals that should never be recommended, like friends and blocked users
+    pub excluded: Vec<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileRecommendation {
+    pub profile: ProfileSummary,
+    // Score between 0 and 1, higher means more overlap with the caller
+    pub score: f64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileSummary {
+    pub principal: Principal,
+    pub identifier: Principal,
+    pub username: String,
+    pub display_name: String,
+    pub profile_image: Asset,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FriendRequestWithProfileResponse {
+    pub request: FriendRequestResponse,
+    // Summary of the other party of the request, only set when requested and the profile is visible
+    pub profile: Option<ProfileSummary>,
+}
+
+// Principals that can use the admin methods next to the controllers of the canister
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct AdminConfig {
+    pub admins: Vec<Principal>,
+}
+
+impl Storable for AdminConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FriendRequestLimits {
+    // Maximum number of friend requests a principal can send per hour
+    pub per_hour: u64,
+    // Maximum number of friend requests a principal can send per day
+    pub per_day: u64,
+    // Maximum number of sent friend requests of a principal that are waiting for a response
+    pub max_pending: u64,
+}
+
+impl Default for FriendRequestLimits {
+    fn default() -> Self {
+        Self {
+            per_hour: 10,
+            per_day: 50,
+            max_pending: 100,
+        }
+    }
+}
+
+impl Storable for FriendRequestLimits {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// The times a principal sent friend requests in the last day, used for the rate limits
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct SentFriendRequests {
+    pub sent_on: Vec<u64>,
+}
+
+impl Storable for SentFriendRequests {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct FriendRequestResponse {
     pub id: u64,
@@ -276,4 +798,118 @@ pub struct FriendRequestResponse {
     pub message: String,
     pub to: Principal,
     pub created_at: u64,
+    pub expires_at: u64,
+}
+
+// The old usernames of a profile, used for the change cooldown without scanning all old usernames
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct UsernameHistory {
+    pub usernames: Vec<String>,
+    pub last_changed_on: u64,
+}
+
+impl Storable for UsernameHistory {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct UsernameChange {
+    pub principal: Principal,
+    pub changed_on: u64,
+}
+
+impl Storable for UsernameChange {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileDeletion {
+    pub requested_on: u64,
+    pub delete_after: u64,
+    // Set when the profile is erased, scheduled deletions are erased by a timer after `delete_after`
+    pub deleted_on: Option<u64>,
+}
+
+impl Storable for ProfileDeletion {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileTombstone {
+    pub deleted_on: u64,
+}
+
+impl Storable for ProfileTombstone {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileChange {
+    pub identifier: Principal,
+    pub actor: Principal,
+    pub method: String,
+    pub changed_fields: Vec<ProfileFieldChange>,
+    pub created_at: u64,
+}
+
+impl Storable for ProfileChange {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// The old and new value of a changed profile field, both JSON encoded
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileFieldChange {
+    pub field: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProfileChangeResponse {
+    pub id: u64,
+    pub identifier: Principal,
+    pub actor: Principal,
+    pub method: String,
+    pub changed_fields: Vec<ProfileFieldChange>,
+    pub created_at: u64,
 }
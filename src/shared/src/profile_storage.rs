@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_scalable_misc::enums::{application_role_type::ApplicationRole, asset_type::Asset};
+
+use crate::profile_models::{DocumentDetails, Profile, ProfilePrivacy, Wallet};
+
+// Stored profile bytes are wrapped in an envelope: `PROFILE_ENVELOPE_PREFIX`, the schema version
+// as a little endian u16 and the candid encoded profile in the shape of that schema version.
+//
+// When the shape of `Profile` changes in an incompatible way:
+// - copy the current `Profile` struct to a `ProfileV{n}` struct in this file
+// - bump `PROFILE_STORAGE_VERSION`
+// - add a `migrate_v{n}` function that decodes the old shape and maps it to the new `Profile`
+// - add a fixture of the old version and a test that decodes it
+pub const PROFILE_ENVELOPE_PREFIX: &[u8; 4] = b"PRFL";
+pub const PROFILE_STORAGE_VERSION: u16 = 2;
+
+// Profiles that were stored before the envelope was introduced are plain candid and treated as version 0
+const UNVERSIONED: u16 = 0;
+
+// Method to encode a profile in the envelope of the current schema version
+pub fn encode_profile(profile: &Profile) -> Vec<u8> {
+    let mut bytes = PROFILE_ENVELOPE_PREFIX.to_vec();
+    bytes.extend_from_slice(&PROFILE_STORAGE_VERSION.to_le_bytes());
+    bytes.extend(Encode!(profile).expect("Failed to encode profile"));
+    bytes
+}
+
+// Method to decode stored profile bytes of any schema version and migrate them to the current `Profile`
+pub fn decode_profile(bytes: &[u8]) -> Profile {
+    let (version, payload) = get_profile_version(bytes);
+
+    match version {
+        UNVERSIONED => migrate_v0(payload),
+        1 => migrate_v1(payload),
+        PROFILE_STORAGE_VERSION => {
+            Decode!(payload, Profile).expect("Failed to decode profile (version 2)")
+        }
+        _ => panic!("Unsupported profile storage version {}", version),
+    }
+}
+
+// Method to get the schema version and the payload from stored profile bytes
+pub fn get_profile_version(bytes: &[u8]) -> (u16, &[u8]) {
+    let header_length = PROFILE_ENVELOPE_PREFIX.len() + 2;
+
+    if bytes.len() < header_length || !bytes.starts_with(PROFILE_ENVELOPE_PREFIX) {
+        return (UNVERSIONED, bytes);
+    }
+
+    let version = u16::from_le_bytes([
+        bytes[PROFILE_ENVELOPE_PREFIX.len()],
+        bytes[PROFILE_ENVELOPE_PREFIX.len() + 1],
+    ]);
+    (version, &bytes[header_length..])
+}
+
+// Version 0 profiles have the same shape as version 1, only the envelope is missing
+fn migrate_v0(payload: &[u8]) -> Profile {
+    let profile = Decode!(payload, ProfileV1).expect("Failed to decode profile (version 0)");
+    profile.into()
+}
+
+// Version 1 profiles have no notification preferences, they receive all notifications
+fn migrate_v1(payload: &[u8]) -> Profile {
+    let profile = Decode!(payload, ProfileV1).expect("Failed to decode profile (version 1)");
+    profile.into()
+}
+
+// The shape of `Profile` in version 0 and 1
+#[derive(CandidType, Deserialize)]
+struct ProfileV1 {
+    principal: Principal,
+    member_identifier: Principal,
+    username: String,
+    display_name: String,
+    application_role: ApplicationRole,
+    first_name: String,
+    last_name: String,
+    privacy: ProfilePrivacy,
+    about: String,
+    email: String,
+    date_of_birth: u64,
+    city: String,
+    state_or_province: String,
+    country: String,
+    profile_image: Asset,
+    banner_image: Asset,
+    skills: Vec<u32>,
+    interests: Vec<u32>,
+    causes: Vec<u32>,
+    website: String,
+    code_of_conduct: DocumentDetails,
+    privacy_policy: Option<DocumentDetails>,
+    terms_of_service: Option<DocumentDetails>,
+    wallets: HashMap<Principal, Wallet>,
+    starred: HashMap<Principal, String>,
+    relations: HashMap<Principal, String>,
+    extra: String,
+    updated_on: u64,
+    created_on: u64,
+}
+
+impl From<ProfileV1> for Profile {
+    fn from(profile: ProfileV1) -> Self {
+        Self {
+            principal: profile.principal,
+            member_identifier: profile.member_identifier,
+            username: profile.username,
+            display_name: profile.display_name,
+            application_role: profile.application_role,
+            first_name: profile.first_name,
+            last_name: profile.last_name,
+            privacy: profile.privacy,
+            about: profile.about,
+            email: profile.email,
+            date_of_birth: profile.date_of_birth,
+            city: profile.city,
+            state_or_province: profile.state_or_province,
+            country: profile.country,
+            profile_image: profile.profile_image,
+            banner_image: profile.banner_image,
+            skills: profile.skills,
+            interests: profile.interests,
+            causes: profile.causes,
+            website: profile.website,
+            code_of_conduct: profile.code_of_conduct,
+            privacy_policy: profile.privacy_policy,
+            terms_of_service: profile.terms_of_service,
+            wallets: profile.wallets,
+            starred: profile.starred,
+            relations: profile.relations,
+            extra: profile.extra,
+            notification_preferences: Default::default(),
+            updated_on: profile.updated_on,
+            created_on: profile.created_on,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The fixtures are stored profiles of every schema version, with the username "fixture"
+    const FIXTURES: [(u16, &[u8]); 3] = [
+        (0, include_bytes!("../fixtures/profile_v0.bin")),
+        (1, include_bytes!("../fixtures/profile_v1.bin")),
+        (2, include_bytes!("../fixtures/profile_v2.bin")),
+    ];
+
+    #[test]
+    fn decodes_every_historical_version() {
+        for (version, bytes) in FIXTURES {
+            assert_eq!(get_profile_version(bytes).0, version);
+
+            let profile = decode_profile(bytes);
+            assert_eq!(profile.username, "fixture");
+            assert_eq!(profile.display_name, "Fixture profile");
+            assert_eq!(profile.skills, vec![1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn encodes_the_current_version() {
+        let profile = decode_profile(FIXTURES[0].1);
+        let bytes = encode_profile(&profile);
+
+        assert_eq!(get_profile_version(&bytes).0, PROFILE_STORAGE_VERSION);
+        assert_eq!(decode_profile(&bytes).username, profile.username);
+    }
+}